@@ -1,7 +1,7 @@
 use std::env;
 use std::path::PathBuf;
 
-use igo::Tagger;
+use igo::{Formatter, Tagger};
 
 fn main() {
     let dic_dir = PathBuf::from(env::var("IGO_DIC").unwrap_or("data/ipadic".to_string()));
@@ -9,10 +9,7 @@ fn main() {
     let text = "すもももももも\u{1F351}もものうち";
 
     let results = tagger.parse(text);
-    for ref m in &results {
-        println!("{}\t{}", m.surface, m.feature);
-    }
-    println!("EOS");
+    print!("{}", Formatter::preset("default").unwrap().format(&tagger, text, &results));
 
     for ref m in results {
         println!("{:?}", m);
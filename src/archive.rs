@@ -0,0 +1,100 @@
+//! アーカイブファイルに格納したバイナリ辞書の読み込み
+//!
+//! zipアーカイブは`zip`フィーチャー、tarアーカイブは`tar`フィーチャー、gzip圧縮したtarアーカイブは
+//! `gzip`フィーチャーを有効にすると読み込める。
+//! アーカイブ内のファイルは、アーカイブのルートからのパス(例: `ipadic/word2id`)で参照される.
+//! サブディレクトリに置かれた辞書は`SubDir`で読み込む
+
+#[cfg(feature = "tar")]
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+
+#[cfg(feature = "tar")]
+use crate::storage::DicFile;
+use crate::util::DirLike;
+
+/// DirLike implement for Zip archive
+#[cfg(feature = "zip")]
+impl<R: io::Read + io::Seek> DirLike for zip::ZipArchive<R> {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        Ok(self.by_name(path).map_err(zip_error)?.size())
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        // アーカイブ内のファイルはアーカイブを借用するため、内容を読み込んでから返す
+        let mut file = self.by_name(path).map_err(zip_error)?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf)?;
+        Ok(Box::new(io::Cursor::new(buf)))
+    }
+}
+
+#[cfg(feature = "zip")]
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    match e {
+        zip::result::ZipError::Io(e) => e,
+        zip::result::ZipError::FileNotFound => {
+            io::Error::new(io::ErrorKind::NotFound, e.to_string())
+        }
+        _ => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+/// tarアーカイブ
+///
+/// tarアーカイブは任意の位置のファイルを直接読み込めないため、作成時に全ての内容をメモリ上に展開する
+#[cfg(feature = "tar")]
+pub struct TarArchive {
+    files: HashMap<String, Vec<u8>>,
+}
+
+#[cfg(feature = "tar")]
+impl TarArchive {
+    /// tarアーカイブを読み込む
+    /// # Arguments
+    /// * `reader` - tarアーカイブの内容
+    pub fn new<R: Read>(reader: R) -> io::Result<TarArchive> {
+        let mut files = HashMap::new();
+        for entry in tar::Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let path = path.trim_start_matches("./").to_string();
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf)?;
+            files.insert(path, buf);
+        }
+        Ok(TarArchive { files })
+    }
+
+    /// gzip圧縮したtarアーカイブ(`*.tar.gz`)を読み込む
+    /// # Arguments
+    /// * `reader` - gzip圧縮したtarアーカイブの内容
+    #[cfg(feature = "gzip")]
+    pub fn from_gz<R: Read>(reader: R) -> io::Result<TarArchive> {
+        TarArchive::new(flate2::read::GzDecoder::new(reader))
+    }
+
+    /// 格納されているファイルのパスのリスト
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|name| name.as_str())
+    }
+}
+
+#[cfg(feature = "tar")]
+impl DirLike for TarArchive {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        self.files.file_size(path)
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        self.files.open(path)
+    }
+
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        self.files.open_dic_file(path)
+    }
+}
@@ -1,5 +1,8 @@
 use std::env;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use getopts::Options;
 
@@ -7,7 +10,38 @@ use igo::dictionary::build::*;
 
 fn print_usage(program: &str, opts: Options) {
     println!("{}", opts.usage(&format!(
-        "Usage:\n {} [options] <output directory> <input directory> <encoding>", program)));
+        "Usage:\n {0} [options] <output directory> <input directory> <encoding>\n \
+         {0} --check [options] <input directory> <encoding>", program)));
+}
+
+/// テキスト辞書を検査して、見つかった問題を全て表示する
+fn check_dic(input_dir: &Path, delimiter: &str, encoding: &str) -> AppResult<i32> {
+    let mut builder = DictionaryBuilder::from_dir(input_dir, encoding)?;
+    builder.set_delimiter(delimiter);
+    let report = builder.validate()?;
+    print!("{}", report);
+    println!("{} errors, {} warnings", report.errors().count(), report.warnings().count());
+    Ok(if report.has_errors() { 1 } else { 0 })
+}
+
+/// テキスト辞書からバイナリ辞書を構築して、進捗を表示する
+fn build(input_dir: &Path, output_dir: &Path, bundle_path: Option<&Path>, delimiter: &str, encoding: &str,
+         keep_homographs: bool) -> AppResult<i32> {
+    let start_time = Instant::now();
+    let mut builder = DictionaryBuilder::from_dir(input_dir, encoding)?;
+    builder.set_delimiter(delimiter);
+    builder.set_keep_homographs(keep_homographs);
+    builder.set_progress(|step| println!("### {}", step));
+    let files = builder.build()?;
+    write_dir(&files, output_dir)?;
+    if let Some(bundle_path) = bundle_path {
+        write_bundle(&files, BufWriter::new(File::create(bundle_path)?))?;
+    }
+
+    let elapsed = start_time.elapsed();
+    println!("DONE");
+    println!("elapsed: {} ms", elapsed.as_secs_f64() * 1000.0);
+    Ok(0)
 }
 
 fn build_dic_cli() -> AppResult<i32> {
@@ -16,6 +50,9 @@ fn build_dic_cli() -> AppResult<i32> {
 
     let mut opts = Options::new();
     opts.optopt("d", "delimiter", "set delimiter to STR.", "STR");
+    opts.optopt("b", "bundle", "also write the dictionary as a single bundle FILE.", "FILE");
+    opts.optflag("c", "check", "only validate the input and report all problems.");
+    opts.optflag("k", "keep-homographs", "keep all entries with the same surface and context ids.");
     opts.optflag("v", "verbose", "enable verbose mode.");
     opts.optflag("", "help", "show this usage message.");
     let matches = match opts.parse(&args[1..]) {
@@ -27,13 +64,25 @@ fn build_dic_cli() -> AppResult<i32> {
     };
     if matches.opt_present("help") {
         print_usage(&program, opts);
-        return Ok(1);
+        return Ok(0);
     }
     if matches.opt_present("verbose") {
         simple_logger::init().unwrap();
     }
     let delimiter = matches.opt_str("d").unwrap_or_else(|| ",".to_string());
+    let bundle_path = matches.opt_str("b");
+    let check = matches.opt_present("check");
+    let keep_homographs = matches.opt_present("keep-homographs");
     let mut args = matches.free.into_iter();
+    if check {
+        return match (args.next(), args.next()) {
+            (Some(input_dir), Some(encoding)) => check_dic(Path::new(&input_dir), &delimiter, &encoding),
+            _ => {
+                print_usage(&program, opts);
+                Ok(1)
+            }
+        };
+    }
     let output_dir = if let Some(s) = args.next() {
         PathBuf::from(s)
     } else {
@@ -53,12 +102,15 @@ fn build_dic_cli() -> AppResult<i32> {
         return Ok(1);
     };
 
-    build_dic(&input_dir, &output_dir, delimiter, &encoding)
+    build(&input_dir, &output_dir, bundle_path.as_deref().map(Path::new), &delimiter, &encoding, keep_homographs)
 }
 
 fn main() {
     match build_dic_cli() {
         Ok(exit_code) => std::process::exit(exit_code),
-        Err(error) => eprintln!("Error: {}", error)
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,179 @@
+//! 単一ファイルにまとめたバイナリ辞書(バンドル)の読み書き
+//!
+//! バンドルの書式. 数値は全てリトルエンディアンで格納される
+//!
+//! | 位置 | サイズ | 内容 |
+//! |------|--------|------|
+//! | 0    | 8      | マジックナンバー `IGO-BNDL` |
+//! | 8    | 4      | バンドルの書式のバージョン |
+//! | 12   | 4      | 格納されているファイルの数 |
+//! | 16   | 48 × ファイル数 | 目次. ファイル名(32バイト, 末尾はNULで埋める)、開始位置(8バイト)、サイズ(8バイト) |
+//!
+//! 目次に続いて各ファイルの内容が8バイト境界に揃えて格納される
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::storage::{padding_len, DicFile};
+use crate::util::DirLike;
+
+/// バンドルの先頭に置かれるマジックナンバー
+const MAGIC: &[u8; 8] = b"IGO-BNDL";
+/// バンドルの書式のバージョン
+const BUNDLE_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16;
+const NAME_SIZE: usize = 32;
+const ENTRY_SIZE: usize = NAME_SIZE + 8 + 8;
+const ALIGNMENT: usize = 8;
+
+fn invalid_data<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn align(pos: usize) -> usize {
+    pos + padding_len(pos, ALIGNMENT)
+}
+
+/// ファイルをまとめてバンドルとして書き出す
+/// # Arguments
+/// * `files`  - ファイル名と内容の組のリスト
+/// * `writer` - バンドルの書き出し先
+pub(crate) fn write<W: Write>(files: &[(&str, &[u8])], mut writer: W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&BUNDLE_VERSION.to_le_bytes())?;
+    writer.write_all(&(files.len() as u32).to_le_bytes())?;
+
+    let mut offset = align(HEADER_SIZE + ENTRY_SIZE * files.len());
+    let mut offsets = Vec::with_capacity(files.len());
+    for (name, data) in files {
+        if name.len() > NAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("file name too long for a bundle: {}", name),
+            ));
+        }
+        let mut name_buf = [0u8; NAME_SIZE];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        writer.write_all(&name_buf)?;
+        writer.write_all(&(offset as u64).to_le_bytes())?;
+        writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        offsets.push(offset);
+        offset = align(offset + data.len());
+    }
+
+    let mut pos = HEADER_SIZE + ENTRY_SIZE * files.len();
+    for ((_, data), offset) in files.iter().zip(offsets) {
+        writer.write_all(&vec![0u8; offset - pos])?;
+        writer.write_all(data)?;
+        pos = offset + data.len();
+    }
+    writer.flush()
+}
+
+/// 単一ファイルにまとめたバイナリ辞書
+///
+/// 格納されているファイルを`DirLike`として読み込める
+pub struct Bundle<'a> {
+    data: Cow<'a, [u8]>,
+    entries: Vec<(String, Range<usize>)>,
+}
+
+impl<'a> Bundle<'a> {
+    /// バンドルの内容から作成する
+    ///
+    /// `include_bytes!`で埋め込んだ辞書などを、バンドル全体を複製せずに参照できる.
+    /// ただし辞書の読み込み時には、格納されている各ファイルの内容を複製する
+    /// # Arguments
+    /// * `data` - バンドルの内容
+    pub fn new<D: Into<Cow<'a, [u8]>>>(data: D) -> io::Result<Bundle<'a>> {
+        let data = data.into();
+        let read_u32 = |pos: usize| {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(&data[pos..(pos + 4)]);
+            u32::from_le_bytes(buf)
+        };
+        let read_u64 = |pos: usize| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&data[pos..(pos + 8)]);
+            u64::from_le_bytes(buf) as usize
+        };
+
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("not a dictionary bundle"));
+        }
+        let version = read_u32(8);
+        if version != BUNDLE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported bundle version: {} (supported: {})",
+                version, BUNDLE_VERSION
+            )));
+        }
+        let count = read_u32(12) as usize;
+        let toc_end = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE));
+        if toc_end.filter(|&end| end <= data.len()).is_none() {
+            return Err(invalid_data("bundle table of contents is truncated"));
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let pos = HEADER_SIZE + ENTRY_SIZE * i;
+            let name = &data[pos..(pos + NAME_SIZE)];
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(NAME_SIZE);
+            let name = String::from_utf8(name[..name_len].to_vec())
+                .map_err(|_| invalid_data("invalid file name in bundle"))?;
+            let (offset, len) = (read_u64(pos + NAME_SIZE), read_u64(pos + NAME_SIZE + 8));
+            let end = offset.checked_add(len).filter(|&end| end <= data.len());
+            if end.is_none() {
+                return Err(invalid_data(format!("{}: bundle is truncated", name)));
+            }
+            entries.push((name, offset..(offset + len)));
+        }
+        Ok(Bundle { data, entries })
+    }
+
+    /// 格納されているファイル名のリスト
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn entry(&self, path: &str) -> io::Result<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == path)
+            .map(|(_, range)| &self.data[range.clone()])
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: not found in bundle", path),
+                )
+            })
+    }
+}
+
+impl Bundle<'static> {
+    /// バンドルのファイルを読み込む
+    /// # Arguments
+    /// * `path` - バンドルのファイルのパス
+    pub fn from_file(path: &Path) -> io::Result<Bundle<'static>> {
+        Bundle::new(fs::read(path)?)
+    }
+}
+
+impl DirLike for Bundle<'_> {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        Ok(self.entry(path)?.len() as u64)
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        Ok(Box::new(io::Cursor::new(self.entry(path)?.to_vec())))
+    }
+
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        DicFile::from_bytes(path, self.entry(path)?.to_vec())
+    }
+}
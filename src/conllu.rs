@@ -0,0 +1,249 @@
+//! CoNLL-U形式での解析結果の出力
+//!
+//! 品詞はUPOS列に、活用などの情報はFEATS列に、素性のパターンと値の対応表(`ConlluMapping`)に従って変換する。
+//! 係り受けは解析しないため、HEAD, DEPREL, DEPSの列は常に`_`になる
+
+use std::io::{self, Write};
+
+use crate::feature::{feature_matches, split_feature};
+use crate::morpheme::Morpheme;
+
+/// 素性からUPOSとFEATSの値への対応表
+///
+/// パターンは`Constraints::add_span`と同じく、カンマ区切りの各項目を素性の先頭から順に比較し、`*`は任意の値に一致する
+#[derive(Debug, Clone)]
+pub struct ConlluMapping {
+    upos: Vec<(String, String)>,
+    feats: Vec<(String, String)>,
+    default_upos: String,
+}
+
+impl ConlluMapping {
+    /// 規則を持たない対応表を作成する. UPOSは全て`X`になる
+    pub fn new() -> ConlluMapping {
+        ConlluMapping {
+            upos: Vec::new(),
+            feats: Vec::new(),
+            default_upos: "X".to_string(),
+        }
+    }
+
+    /// IPADIC(およびNAIST-jdic)の品詞体系用の対応表
+    pub fn ipadic() -> ConlluMapping {
+        let mut mapping = ConlluMapping::new();
+        for &(pattern, upos) in &[
+            ("その他", "X"),
+            ("フィラー", "INTJ"),
+            ("記号", "SYM"),
+            ("記号,句点", "PUNCT"),
+            ("記号,読点", "PUNCT"),
+            ("記号,括弧開", "PUNCT"),
+            ("記号,括弧閉", "PUNCT"),
+            ("接頭詞", "NOUN"),
+            ("助詞", "ADP"),
+            ("助詞,接続助詞", "SCONJ"),
+            ("助動詞", "AUX"),
+            ("感動詞", "INTJ"),
+            ("接続詞", "CCONJ"),
+            ("連体詞", "DET"),
+            ("副詞", "ADV"),
+            ("形容詞", "ADJ"),
+            ("動詞", "VERB"),
+            ("動詞,非自立", "AUX"),
+            ("名詞", "NOUN"),
+            ("名詞,形容動詞語幹", "ADJ"),
+            ("名詞,数", "NUM"),
+            ("名詞,代名詞", "PRON"),
+            ("名詞,固有名詞", "PROPN"),
+        ] {
+            mapping.add_upos(pattern, upos);
+        }
+        for &(pattern, feats) in &[
+            ("名詞,数", "NumType=Card"),
+            ("名詞,代名詞", "PronType=Prs"),
+            ("助動詞,*,*,*,特殊・タ", "Tense=Past"),
+            ("助動詞,*,*,*,特殊・ナイ", "Polarity=Neg"),
+            ("助動詞,*,*,*,特殊・ヌ", "Polarity=Neg"),
+            ("*,*,*,*,*,仮定形", "Mood=Cnd"),
+            ("*,*,*,*,*,命令ｅ", "Mood=Imp"),
+            ("*,*,*,*,*,命令ｉ", "Mood=Imp"),
+            ("*,*,*,*,*,命令ｒｏ", "Mood=Imp"),
+            ("*,*,*,*,*,命令ｙｏ", "Mood=Imp"),
+        ] {
+            mapping.add_feats(pattern, feats);
+        }
+        mapping
+    }
+
+    /// UniDicの品詞体系用の対応表
+    pub fn unidic() -> ConlluMapping {
+        let mut mapping = ConlluMapping::new();
+        for &(pattern, upos) in &[
+            ("空白", "SYM"),
+            ("記号", "SYM"),
+            ("補助記号", "SYM"),
+            ("補助記号,句点", "PUNCT"),
+            ("補助記号,読点", "PUNCT"),
+            ("補助記号,括弧開", "PUNCT"),
+            ("補助記号,括弧閉", "PUNCT"),
+            ("接頭辞", "NOUN"),
+            ("接尾辞", "NOUN"),
+            ("接尾辞,形容詞的", "ADJ"),
+            ("接尾辞,形状詞的", "ADJ"),
+            ("接尾辞,動詞的", "VERB"),
+            ("助詞", "ADP"),
+            ("助詞,接続助詞", "SCONJ"),
+            ("助動詞", "AUX"),
+            ("感動詞", "INTJ"),
+            ("接続詞", "CCONJ"),
+            ("連体詞", "DET"),
+            ("副詞", "ADV"),
+            ("形状詞", "ADJ"),
+            ("形容詞", "ADJ"),
+            ("動詞", "VERB"),
+            ("代名詞", "PRON"),
+            ("名詞", "NOUN"),
+            ("名詞,数詞", "NUM"),
+            ("名詞,固有名詞", "PROPN"),
+        ] {
+            mapping.add_upos(pattern, upos);
+        }
+        for &(pattern, feats) in &[
+            ("名詞,数詞", "NumType=Card"),
+            ("代名詞", "PronType=Prs"),
+            ("助動詞,*,*,*,助動詞-タ", "Tense=Past"),
+            ("助動詞,*,*,*,助動詞-ナイ", "Polarity=Neg"),
+            ("助動詞,*,*,*,助動詞-ヌ", "Polarity=Neg"),
+            ("*,*,*,*,*,仮定形", "Mood=Cnd"),
+            ("*,*,*,*,*,命令形", "Mood=Imp"),
+        ] {
+            mapping.add_feats(pattern, feats);
+        }
+        mapping
+    }
+
+    /// UPOSの規則を追加する. 後から追加した規則ほど優先される
+    /// # Arguments
+    /// * `pattern` - 素性のパターン
+    /// * `upos`    - パターンに一致した形態素のUPOS
+    pub fn add_upos(&mut self, pattern: &str, upos: &str) {
+        self.upos.insert(0, (pattern.to_string(), upos.to_string()));
+    }
+
+    /// FEATSの規則を追加する. 一致した全ての規則の値が出力される
+    /// # Arguments
+    /// * `pattern` - 素性のパターン
+    /// * `feats`   - パターンに一致した形態素のFEATS. `Name=Value`を`|`で区切って複数指定できる
+    pub fn add_feats(&mut self, pattern: &str, feats: &str) {
+        self.feats.push((pattern.to_string(), feats.to_string()));
+    }
+
+    /// どの規則にも一致しない場合のUPOSを設定する
+    pub fn set_default_upos(&mut self, upos: &str) {
+        self.default_upos = upos.to_string();
+    }
+
+    /// 素性に対応するUPOSを返す
+    pub fn upos(&self, feature: &str) -> &str {
+        self.upos
+            .iter()
+            .find(|(pattern, _)| feature_matches(pattern, feature))
+            .map_or(&self.default_upos, |(_, upos)| upos)
+    }
+
+    /// 素性に対応するFEATSを返す. 対応する値が無い場合は`None`を返す
+    ///
+    /// 値は名前の順(大文字と小文字を区別しない)に並べ、重複を除いて`|`で連結する
+    pub fn feats(&self, feature: &str) -> Option<String> {
+        let mut feats: Vec<&str> = self
+            .feats
+            .iter()
+            .filter(|(pattern, _)| feature_matches(pattern, feature))
+            .flat_map(|(_, feats)| feats.split('|'))
+            .filter(|f| !f.is_empty())
+            .collect();
+        if feats.is_empty() {
+            return None;
+        }
+        feats.sort_by_key(|f| f.to_lowercase());
+        feats.dedup();
+        Some(feats.join("|"))
+    }
+}
+
+impl Default for ConlluMapping {
+    fn default() -> Self {
+        ConlluMapping::ipadic()
+    }
+}
+
+/// 解析結果をCoNLL-U形式で書き出す
+pub struct ConlluWriter<W: Write> {
+    writer: W,
+    mapping: ConlluMapping,
+    sent_id: usize,
+}
+
+impl<W: Write> ConlluWriter<W> {
+    /// # Arguments
+    /// * `writer`  - 出力先
+    /// * `mapping` - 素性からUPOSとFEATSへの対応表
+    pub fn new(writer: W, mapping: ConlluMapping) -> ConlluWriter<W> {
+        ConlluWriter {
+            writer,
+            mapping,
+            sent_id: 0,
+        }
+    }
+
+    /// 一文の解析結果を書き出す. 形態素が無い場合は何も書き出さない
+    ///
+    /// 文には1から順に`sent_id`が振られる
+    /// # Arguments
+    /// * `text`      - 解析したテキスト. 形態素の`byte_range`はこのテキスト内の位置でなければならない
+    /// * `morphemes` - 解析結果
+    pub fn write_sentence(&mut self, text: &str, morphemes: &[Morpheme]) -> io::Result<()> {
+        if morphemes.is_empty() {
+            return Ok(());
+        }
+        self.sent_id += 1;
+        writeln!(self.writer, "# sent_id = {}", self.sent_id)?;
+        let line: String = text
+            .trim()
+            .chars()
+            .map(|c| if c == '\n' || c == '\r' { ' ' } else { c })
+            .collect();
+        writeln!(self.writer, "# text = {}", line)?;
+
+        for (i, m) in morphemes.iter().enumerate() {
+            let lemma = m.base_form();
+            let xpos = split_feature(m.feature)
+                .take(m.schema.pos_levels)
+                .filter(|f| f != "*" && !f.is_empty())
+                .collect::<Vec<_>>()
+                .join("-");
+            let feats = self.mapping.feats(m.feature);
+            let space_after = match text.get(m.byte_range.end..).and_then(|s| s.chars().next()) {
+                Some(c) if !c.is_whitespace() => "SpaceAfter=No",
+                _ => "_",
+            };
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t_\t_\t_\t{}",
+                i + 1,
+                m.surface,
+                lemma.as_deref().unwrap_or(m.surface),
+                self.mapping.upos(m.feature),
+                if xpos.is_empty() { "_" } else { &xpos },
+                feats.as_deref().unwrap_or("_"),
+                space_after
+            )?;
+        }
+        writeln!(self.writer)
+    }
+
+    /// 出力先を返す
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,133 @@
+use std::ops::Range;
+
+use crate::dictionary::build::{AppError, AppResult};
+use crate::feature::feature_matches;
+
+/// 制約付き解析(部分解析)で使用する制約
+///
+/// 位置は全て解析対象テキストのバイト単位のオフセットで指定する
+#[derive(Debug, Clone, Default)]
+pub struct Constraints {
+    boundaries: Vec<usize>,
+    spans: Vec<(Range<usize>, Option<String>)>,
+}
+
+impl Constraints {
+    pub fn new() -> Constraints {
+        Constraints::default()
+    }
+
+    /// 指定した位置を必ず形態素の境界にする
+    /// # Arguments
+    /// * `pos` - 境界の位置(バイト単位)
+    pub fn add_boundary(&mut self, pos: usize) {
+        self.boundaries.push(pos);
+    }
+
+    /// 指定した範囲を一つの形態素として解析させる
+    /// # Arguments
+    /// * `range`   - 形態素の範囲(バイト単位)
+    /// * `feature` - 形態素の素性のパターン. カンマ区切りの各項目は素性の先頭から順に比較され、`*`は任意の値に一致する
+    pub fn add_span(&mut self, range: Range<usize>, feature: Option<&str>) {
+        self.spans.push((range, feature.map(|s| s.to_string())));
+    }
+
+    /// 制約を解析時に参照しやすい形に変換する
+    ///
+    /// 制約の位置が文字の境界に無い場合、及び範囲の制約が空か重複している場合はエラーを返す
+    pub(crate) fn resolve(&self, text: &str) -> AppResult<ResolvedConstraints> {
+        let len = text.len();
+        let check = |pos: usize| -> AppResult<usize> {
+            if !text.is_char_boundary(pos) {
+                return Err(AppError::Message(format!(
+                    "constraint position {} is not a char boundary of the text (length {})",
+                    pos, len
+                )));
+            }
+            Ok(pos)
+        };
+
+        let mut is_boundary = vec![false; len + 1];
+        let mut spans: Vec<Option<(usize, Option<String>)>> = vec![None; len + 1];
+        for &pos in &self.boundaries {
+            is_boundary[check(pos)?] = true;
+        }
+        let mut covered = vec![false; len + 1];
+        for (range, feature) in &self.spans {
+            let (start, end) = (check(range.start)?, check(range.end)?);
+            if start >= end {
+                return Err(AppError::Message(format!(
+                    "empty span constraint {:?}",
+                    range
+                )));
+            }
+            if covered[start..end].iter().any(|&c| c) {
+                return Err(AppError::Message(format!(
+                    "overlapping span constraint {:?}",
+                    range
+                )));
+            }
+            for c in &mut covered[start..end] {
+                *c = true;
+            }
+            spans[start] = Some((end, feature.clone()));
+        }
+        for (start, span) in spans.iter().enumerate() {
+            if let Some((end, _)) = *span {
+                // 範囲の内側の境界は無視する
+                for b in &mut is_boundary[(start + 1)..end] {
+                    *b = false;
+                }
+                is_boundary[start] = true;
+                is_boundary[end] = true;
+            }
+        }
+
+        // next_boundary[i] = i より後ろで最初の境界の位置
+        let mut next_boundary = vec![len; len + 1];
+        for i in (0..len).rev() {
+            next_boundary[i] = if is_boundary[i + 1] {
+                i + 1
+            } else {
+                next_boundary[i + 1]
+            };
+        }
+
+        Ok(ResolvedConstraints {
+            next_boundary,
+            spans,
+        })
+    }
+}
+
+/// 位置ごとの表に変換した制約
+pub(crate) struct ResolvedConstraints {
+    next_boundary: Vec<usize>,
+    spans: Vec<Option<(usize, Option<String>)>>,
+}
+
+impl ResolvedConstraints {
+    /// `start`から始まる形態素が取りうる最大の終了位置
+    pub fn next_boundary(&self, start: usize) -> usize {
+        self.next_boundary[start]
+    }
+
+    /// `start..end`の範囲の形態素が制約を満たすかどうか
+    /// # Arguments
+    /// * `feature` - 形態素の素性. `None`の場合は素性のパターンを無視する
+    pub fn accepts(&self, start: usize, end: usize, feature: Option<&str>) -> bool {
+        if end > self.next_boundary[start] {
+            return false;
+        }
+        match self.spans[start] {
+            Some((span_end, ref pattern)) => {
+                span_end == end
+                    && match (pattern, feature) {
+                        (Some(pattern), Some(feature)) => feature_matches(pattern, feature),
+                        _ => true,
+                    }
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use glob::glob;
+use log::info;
+
+use crate::bundle;
+use crate::dictionary::build::charcategory::{self, CharCategory};
+use crate::dictionary::build::matrix::build_matrix;
+use crate::dictionary::build::validate::{ValidationReport, Validator};
+use crate::dictionary::build::worddic::{build_word_info, build_word_trie, WordSource};
+use crate::dictionary::build::{AppError, AppResult};
+use crate::storage::{DicFile, DIC_FILES};
+use crate::trie::Searcher;
+use crate::util::ReadLine;
+
+/// 辞書の構築に使用するテキストの読み込み元
+#[derive(Debug, Clone)]
+pub enum DicSource {
+    /// ファイル
+    File(PathBuf),
+    /// メモリ上のデータ. 名前はエラーの報告に使用する
+    Bytes(String, Vec<u8>),
+}
+
+impl DicSource {
+    /// 読み込み元の内容を全て読み込んで、メモリ上のデータにする
+    /// # Arguments
+    /// * `name`   - エラーの報告に使用する名前
+    /// * `reader` - 読み込み元
+    pub fn from_reader<R: Read>(name: &str, mut reader: R) -> io::Result<DicSource> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(DicSource::Bytes(name.to_string(), data))
+    }
+
+    /// エラーの報告に使用する、読み込み元の名前
+    pub(crate) fn name(&self) -> &Path {
+        match *self {
+            DicSource::File(ref path) => path,
+            DicSource::Bytes(ref name, _) => Path::new(name),
+        }
+    }
+
+    pub(crate) fn open(&self, encoding: &str) -> AppResult<ReadLine<'_>> {
+        match *self {
+            DicSource::File(ref path) => ReadLine::new(path, encoding),
+            DicSource::Bytes(ref name, ref data) => {
+                ReadLine::from_reader(&data[..], Path::new(name), encoding)
+            }
+        }
+    }
+}
+
+/// 辞書の構築の段階. `DictionaryBuilder::set_progress`で設定した関数に、各段階の開始時に渡される
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildStep {
+    /// 単語の表層形のtrieを構築する
+    WordTrie,
+    /// 単語辞書を構築する
+    WordDic,
+    /// 連接コスト表を構築する
+    Matrix,
+    /// 文字カテゴリの定義を構築する
+    CharCategory,
+}
+
+impl fmt::Display for BuildStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BuildStep::WordTrie => "Build word trie",
+            BuildStep::WordDic => "Build word dictionary",
+            BuildStep::Matrix => "Build matrix",
+            BuildStep::CharCategory => "Build char-category dictionary",
+        })
+    }
+}
+
+/// テキスト辞書からバイナリ辞書を構築する
+///
+/// 入力は`DicSource`で明示的に指定し、構築したバイナリ辞書はファイル名と内容のマップとして得られる。
+/// 得られたマップは`Tagger::load_from_dir`でそのまま読み込める
+pub struct DictionaryBuilder {
+    words: Vec<(DicSource, String)>,
+    unk_def: Option<(DicSource, String)>,
+    char_def: Option<(DicSource, String)>,
+    matrix: Option<DicSource>,
+    delimiter: String,
+    keep_homographs: bool,
+    progress: Option<Box<dyn FnMut(BuildStep)>>,
+}
+
+impl DictionaryBuilder {
+    /// 入力を持たないビルダーを作成する. 項目の区切り文字は`,`
+    pub fn new() -> DictionaryBuilder {
+        DictionaryBuilder {
+            words: Vec::new(),
+            unk_def: None,
+            char_def: None,
+            matrix: None,
+            delimiter: ",".to_string(),
+            keep_homographs: false,
+            progress: None,
+        }
+    }
+
+    /// MeCab形式のテキスト辞書のディレクトリを入力とするビルダーを作成する
+    ///
+    /// ディレクトリ内の`*.csv`, `unk.def`, `char.def`, `matrix.def`を使用する
+    /// # Arguments
+    /// * `input_dir` - テキスト辞書があるディレクトリ
+    /// * `encoding`  - テキスト辞書の文字列エンコーディング
+    pub fn from_dir(input_dir: &Path, encoding: &str) -> AppResult<DictionaryBuilder> {
+        let mut builder = DictionaryBuilder::new();
+        let pattern = input_dir.join("*.csv");
+        let pattern = pattern
+            .to_str()
+            .ok_or_else(|| format!("invalid path: {}", input_dir.display()))?;
+        for entry in glob(pattern)? {
+            match entry {
+                Ok(csv_file) if csv_file.is_file() => {
+                    builder.add_words(DicSource::File(csv_file), encoding)
+                }
+                _ => (),
+            }
+        }
+        builder.set_unk_def(DicSource::File(input_dir.join("unk.def")), encoding);
+        builder.set_char_def(DicSource::File(input_dir.join("char.def")), encoding);
+        builder.set_matrix(DicSource::File(input_dir.join("matrix.def")));
+        Ok(builder)
+    }
+
+    /// 単語辞書(`*.csv`と同じ書式)を追加する
+    /// # Arguments
+    /// * `source`   - 単語辞書
+    /// * `encoding` - 単語辞書の文字列エンコーディング
+    pub fn add_words(&mut self, source: DicSource, encoding: &str) {
+        self.words.push((source, encoding.to_string()));
+    }
+
+    /// 未知語の定義(`unk.def`)を設定する
+    /// # Arguments
+    /// * `source`   - 未知語の定義
+    /// * `encoding` - 未知語の定義の文字列エンコーディング
+    pub fn set_unk_def(&mut self, source: DicSource, encoding: &str) {
+        self.unk_def = Some((source, encoding.to_string()));
+    }
+
+    /// 文字カテゴリの定義(`char.def`)を設定する
+    /// # Arguments
+    /// * `source`   - 文字カテゴリの定義
+    /// * `encoding` - 文字カテゴリの定義の文字列エンコーディング
+    pub fn set_char_def(&mut self, source: DicSource, encoding: &str) {
+        self.char_def = Some((source, encoding.to_string()));
+    }
+
+    /// 連接コスト表(`matrix.def`)を設定する
+    pub fn set_matrix(&mut self, source: DicSource) {
+        self.matrix = Some(source);
+    }
+
+    /// 単語辞書と未知語の定義の、各項目の区切り文字を設定する
+    pub fn set_delimiter(&mut self, delimiter: &str) {
+        self.delimiter = delimiter.to_string();
+    }
+
+    /// 表層形と文脈IDが同じ単語を全て残すかどうかを設定する
+    ///
+    /// 既定では、表層形と左右の文脈IDが同じ単語はコストが最小のもの以外は解析結果に現れないため、辞書から除外する。
+    /// 残した場合、解析ではコストが最小の単語(同じコストの場合は先に定義された単語)が選ばれ、
+    /// 他の単語の素性は`Tagger::homographs`で取得できる
+    pub fn set_keep_homographs(&mut self, keep: bool) {
+        self.keep_homographs = keep;
+    }
+
+    /// 構築の進捗を受け取る関数を設定する
+    pub fn set_progress<F: FnMut(BuildStep) + 'static>(&mut self, progress: F) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// 単語の読み込み元のリスト. 未知語の定義を先頭に含む
+    pub(crate) fn word_sources(&self) -> AppResult<Vec<WordSource<'_>>> {
+        let (unk_def, unk_encoding) = self.unk_def.as_ref().ok_or_else(|| missing("unk.def"))?;
+        let mut sources = vec![WordSource {
+            source: unk_def,
+            encoding: unk_encoding,
+            prefix: charcategory::KEY_PREFIX,
+        }];
+        sources.extend(self.words.iter().map(|(source, encoding)| WordSource {
+            source,
+            encoding,
+            prefix: "",
+        }));
+        Ok(sources)
+    }
+
+    /// 入力を検査し、見つかった全ての問題を報告する
+    ///
+    /// `build`は最初に見つかった誤りでエラーとなるが、`validate`は入力全体を検査して、
+    /// 書式の誤りに加えて、文脈IDの範囲、コストの範囲、重複した単語、素性の項目数、
+    /// 文字カテゴリの定義と未知語の定義の対応を調べる
+    pub fn validate(&self) -> AppResult<ValidationReport> {
+        let (unk_def, unk_encoding) = self.unk_def.as_ref().ok_or_else(|| missing("unk.def"))?;
+        let (char_def, char_encoding) =
+            self.char_def.as_ref().ok_or_else(|| missing("char.def"))?;
+        let matrix = self.matrix.as_ref().ok_or_else(|| missing("matrix.def"))?;
+
+        let mut validator = Validator::new(&self.delimiter);
+        validator.check_matrix(matrix);
+        let unk_categories = validator.check_unk_def(unk_def, unk_encoding);
+        validator.check_words(&self.words);
+        validator.check_char_def(char_def, char_encoding, &unk_categories);
+        Ok(validator.finish())
+    }
+
+    /// バイナリ辞書を構築する
+    /// # Return
+    /// キーがファイル名、値がファイルの内容のマップ
+    pub fn build(mut self) -> AppResult<HashMap<String, Vec<u8>>> {
+        let mut progress = self.progress.take();
+        let sources = self.word_sources()?;
+        let (char_def, char_encoding) =
+            self.char_def.as_ref().ok_or_else(|| missing("char.def"))?;
+        let matrix = self.matrix.as_ref().ok_or_else(|| missing("matrix.def"))?;
+
+        let mut files = HashMap::new();
+        report(&mut progress, BuildStep::WordTrie);
+        let word2id = build_word_trie(&sources, &self.delimiter)?;
+        let wid = Searcher::from_dic_file(DicFile::from_bytes("word2id", word2id.clone())?)?;
+        files.insert("word2id".to_string(), word2id);
+
+        report(&mut progress, BuildStep::WordDic);
+        for (name, data) in build_word_info(&sources, &self.delimiter, &wid, self.keep_homographs)?
+        {
+            files.insert(name.to_string(), data);
+        }
+
+        report(&mut progress, BuildStep::Matrix);
+        files.insert("matrix.bin".to_string(), build_matrix(matrix)?);
+
+        report(&mut progress, BuildStep::CharCategory);
+        let (categories, code2category) = CharCategory::build_bytes(char_def, char_encoding, &wid)?;
+        files.insert("char.category".to_string(), categories);
+        files.insert("code2category".to_string(), code2category);
+
+        Ok(files)
+    }
+
+    /// バイナリ辞書を構築し、ディレクトリに保存する
+    /// # Arguments
+    /// * `output_dir` - 保存先のディレクトリ. 存在しない場合は作成する
+    pub fn build_to_dir(self, output_dir: &Path) -> AppResult<()> {
+        write_dir(&self.build()?, output_dir)
+    }
+
+    /// バイナリ辞書を構築し、単一ファイルのバンドルとして書き出す
+    /// # Arguments
+    /// * `writer` - バンドルの書き出し先
+    pub fn build_bundle<W: Write>(self, writer: W) -> AppResult<()> {
+        write_bundle(&self.build()?, writer)
+    }
+}
+
+/// `DictionaryBuilder::build`で構築したバイナリ辞書を、ディレクトリに保存する
+/// # Arguments
+/// * `files`      - 構築したバイナリ辞書
+/// * `output_dir` - 保存先のディレクトリ. 存在しない場合は作成する
+pub fn write_dir(files: &HashMap<String, Vec<u8>>, output_dir: &Path) -> AppResult<()> {
+    fs::create_dir_all(output_dir)?;
+    for (name, data) in files {
+        fs::write(output_dir.join(name), data)?;
+    }
+    Ok(())
+}
+
+/// `DictionaryBuilder::build`で構築したバイナリ辞書を、単一ファイルのバンドルとして書き出す
+///
+/// 同じ構築結果をディレクトリとバンドルの両方に書き出す場合に使用する
+/// # Arguments
+/// * `files`  - 構築したバイナリ辞書
+/// * `writer` - バンドルの書き出し先
+///
+/// 辞書ファイルが欠けている場合はエラーとなる
+pub fn write_bundle<W: Write>(files: &HashMap<String, Vec<u8>>, writer: W) -> AppResult<()> {
+    let files = DIC_FILES
+        .iter()
+        .map(|name| match files.get(*name) {
+            Some(data) => Ok((*name, &data[..])),
+            None => Err(AppError::from(format!(
+                "{} is missing from the built dictionary",
+                name
+            ))),
+        })
+        .collect::<AppResult<Vec<(&str, &[u8])>>>()?;
+    Ok(bundle::write(&files, writer)?)
+}
+
+fn report(progress: &mut Option<Box<dyn FnMut(BuildStep)>>, step: BuildStep) {
+    info!("{}", step);
+    if let Some(ref mut progress) = *progress {
+        progress(step);
+    }
+}
+
+fn missing(name: &str) -> AppError {
+    AppError::from(format!("{} is not specified", name))
+}
+
+impl Default for DictionaryBuilder {
+    fn default() -> Self {
+        DictionaryBuilder::new()
+    }
+}
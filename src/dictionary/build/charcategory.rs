@@ -1,12 +1,12 @@
 use crate::dictionary::build::*;
-use crate::dictionary::charcategory::{Category, SPACE_CHAR};
+use crate::dictionary::charcategory::{Category, BMP_SIZE, SPACE_CHAR};
+use crate::storage::{DicFile, DicWriter};
 use crate::trie::Searcher;
 use crate::util::*;
-use byteorder::{NativeEndian as NE, WriteBytesExt};
+use byteorder::{LittleEndian as LE, WriteBytesExt};
 use std::cmp;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -14,6 +14,9 @@ use std::rc::Rc;
 pub const KEY_PREFIX: &str = "\x02";
 
 /// 文字カテゴリ定義を保持したバイナリデータを作成する
+///
+/// 単語のtrie(`word2id`)が保存済みのディレクトリに、文字カテゴリ定義のみを構築して保存する.
+/// 辞書全体を構築する場合は`DictionaryBuilder`を使用する
 pub struct CharCategory {
     input_dir: PathBuf,
     encoding: String,
@@ -26,6 +29,7 @@ impl CharCategory {
     /// * `input_dir`  - テキスト単語辞書が配置されているディレクトリのパス
     /// * `encoding`   - テキスト単語辞書の文字列エンコーディング
     /// * `output_dir` - バイナリ単語辞書の保存先ディレクトリ
+    #[deprecated(note = "use `DictionaryBuilder` to build the whole dictionary")]
     pub fn new(input_dir: &Path, encoding: &str, output_dir: &Path) -> CharCategory {
         CharCategory {
             input_dir: input_dir.to_owned(),
@@ -36,26 +40,46 @@ impl CharCategory {
 
     /// 文字カテゴリ定義のバイナリデータを作成する
     pub fn build(self) -> AppResult<()> {
+        let wid = DicFile::from_bytes("word2id", fs::read(self.output_dir.join("word2id"))?)?;
+        let (categories, code2category) = CharCategory::build_bytes(
+            &DicSource::File(self.input_dir.join("char.def")),
+            &self.encoding,
+            &Searcher::from_dic_file(wid)?,
+        )?;
+        fs::write(self.output_dir.join("char.category"), categories)?;
+        fs::write(self.output_dir.join("code2category"), code2category)?;
+        Ok(())
+    }
+
+    /// 文字カテゴリ定義のバイナリデータを作成する
+    /// # Arguments
+    /// * `source`   - 文字カテゴリの定義(`char.def`)
+    /// * `encoding` - 文字カテゴリの定義の文字列エンコーディング
+    /// * `wid`      - 単語の表層形のtrie. 未知語の定義のカテゴリ名が登録されている必要がある
+    /// # Return
+    /// `char.category`と`code2category`の内容の組
+    pub(crate) fn build_bytes(
+        source: &DicSource,
+        encoding: &str,
+        wid: &Searcher,
+    ) -> AppResult<(Vec<u8>, Vec<u8>)> {
         // 文字カテゴリの定義を取得する
-        let ccmap = self.parse_char_category_def()?;
+        let ccmap = CharCategory::parse_char_category_def(source, encoding, wid)?;
 
-        {
-            // 文字カテゴリの定義を保存する
-            let mut categories: Vec<&Category> = Vec::new();
-            for e in ccmap.values() {
-                categories.push(e);
-            }
-            self.save_char_category_map(categories)?;
-        }
+        // 文字カテゴリの定義を保存する
+        let categories = CharCategory::save_char_category_map(ccmap.values().collect())?;
 
         // 文字とカテゴリのマッピングを取得/保存する
-        self.build_code_category_map(ccmap)?;
+        let code2category = CharCategory::build_code_category_map(source, encoding, ccmap)?;
 
-        Ok(())
+        Ok((categories, code2category))
     }
 
-    fn parse_char_category_def(&self) -> AppResult<HashMap<String, Category>> {
-        let path = self.input_dir.join("char.def");
+    fn parse_char_category_def(
+        source: &DicSource,
+        encoding: &str,
+        srch: &Searcher,
+    ) -> AppResult<HashMap<String, Category>> {
         let too_few_fields = |rl: &ReadLine| -> AppError {
             rl.parse_error("Invalid char category definition (too few fields).")
         };
@@ -72,8 +96,7 @@ impl CharCategory {
                 }
             })
         };
-        let mut rl = ReadLine::new(path.as_path(), &self.encoding)?;
-        let srch = Searcher::from_path(self.output_dir.join("word2id").as_path())?;
+        let mut rl = source.open(encoding)?;
         let mut map = HashMap::new();
 
         let mut s = String::new();
@@ -96,10 +119,7 @@ impl CharCategory {
                 .next()
                 .ok_or_else(|| too_few_fields(&rl))
                 .and_then(|s| s.parse().map_err(AppError::from))?;
-            let key_utf16 = (KEY_PREFIX.to_string() + name)
-                .encode_utf16()
-                .collect::<Vec<_>>();
-            let id = srch.search(&key_utf16);
+            let id = srch.search(&(KEY_PREFIX.to_string() + name));
 
             if length < 0 {
                 return Err(rl.parse_error(
@@ -130,32 +150,30 @@ impl CharCategory {
         Ok(map)
     }
 
-    fn save_char_category_map(&self, mut categories: Vec<&Category>) -> AppResult<()> {
-        let mut writer = BufWriter::new(File::create(
-            self.output_dir.join("char.category").as_path(),
-        )?);
+    fn save_char_category_map(mut categories: Vec<&Category>) -> AppResult<Vec<u8>> {
+        let mut writer = DicWriter::new();
         categories.sort();
         for e in categories {
-            writer.write_i32::<NE>(e.id)?;
-            writer.write_i32::<NE>(e.length)?;
-            writer.write_i32::<NE>(if e.invoke { 1 } else { 0 })?;
-            writer.write_i32::<NE>(if e.group { 1 } else { 0 })?;
+            writer.write_i32::<LE>(e.id)?;
+            writer.write_i32::<LE>(e.length)?;
+            writer.write_i32::<LE>(if e.invoke { 1 } else { 0 })?;
+            writer.write_i32::<LE>(if e.group { 1 } else { 0 })?;
         }
-        Ok(writer.flush()?)
+        Ok(writer.into_bytes())
     }
 
-    fn build_code_category_map(&self, map: HashMap<String, Category>) -> AppResult<()> {
-        let mut chars: Vec<Rc<CharId>> = Vec::with_capacity(0x10_000);
-        {
-            let dft = Rc::new(CharId::new(map["DEFAULT"].id));
-            for _ in 0..0x10_000 {
-                chars.push(dft.clone());
-            }
-        }
+    fn build_code_category_map(
+        source: &DicSource,
+        encoding: &str,
+        map: HashMap<String, Category>,
+    ) -> AppResult<Vec<u8>> {
+        let dft = Rc::new(CharId::new(map["DEFAULT"].id));
+        let mut chars: Vec<Rc<CharId>> = vec![dft.clone(); BMP_SIZE];
+        // 基本多言語面より上の範囲の定義. 後に定義したものが優先される
+        let mut supplementary: Vec<(u32, u32, Rc<CharId>)> = Vec::new();
 
         {
-            let path = self.input_dir.join("char.def");
-            let mut rl = ReadLine::new(path.as_path(), &self.encoding)?;
+            let mut rl = source.open(encoding)?;
             let mut s = String::new();
             loop {
                 let len = rl.next(&mut s).map_err(|e| rl.parse_error(e.to_string()))?;
@@ -180,8 +198,9 @@ impl CharCategory {
                     end = beg;
                 }
 
-                if !(0 <= beg && beg <= 0xFFFF && 0 <= end && end <= 0xFFFF && beg <= end) {
-                    return Err(rl.parse_error("Wrong UCS2 code specified."));
+                if !((0..=0x10FFFF).contains(&beg) && (0..=0x10FFFF).contains(&end) && beg <= end)
+                {
+                    return Err(rl.parse_error("Wrong code point specified."));
                 }
 
                 // 文字カテゴリ及び互換カテゴリの取得
@@ -191,7 +210,7 @@ impl CharCategory {
                 })?;
                 let ch = {
                     let mut ch = CharId::new(category.id);
-                    while let Some(f) = ss.next() {
+                    for f in ss {
                         if f.starts_with('#') {
                             break;
                         }
@@ -204,9 +223,12 @@ impl CharCategory {
                 };
 
                 // カテゴリ登録
-                for i in beg..=end {
+                for i in beg..=end.min(BMP_SIZE as i32 - 1) {
                     chars[i as usize] = ch.clone();
                 }
+                if end >= BMP_SIZE as i32 {
+                    supplementary.push((beg.max(BMP_SIZE as i32) as u32, end as u32, ch));
+                }
             }
 
             if chars[SPACE_CHAR as usize].id != map["SPACE"].id {
@@ -214,18 +236,53 @@ impl CharCategory {
             }
         }
 
-        let mut writer = BufWriter::new(File::create(
-            self.output_dir.join("code2category").as_path(),
-        )?);
+        let mut writer = DicWriter::new();
         for c in &chars {
-            writer.write_i32::<NE>(c.id)?;
+            writer.write_i32::<LE>(c.id)?;
         }
         for c in &chars {
-            writer.write_i32::<NE>(c.mask)?;
+            writer.write_i32::<LE>(c.mask)?;
         }
+        for (beg, end, c) in supplementary_ranges(&supplementary, &dft) {
+            writer.write_i32::<LE>(beg as i32)?;
+            writer.write_i32::<LE>(end as i32)?;
+            writer.write_i32::<LE>(c.id)?;
+            writer.write_i32::<LE>(c.mask)?;
+        }
+
+        Ok(writer.into_bytes())
+    }
+}
 
-        Ok(writer.flush()?)
+/// 基本多言語面より上の範囲の定義を、重なりの無い昇順の範囲のリストに変換する
+///
+/// 定義の重なる部分は後に定義したものを使い、定義の無い部分はDEFAULTカテゴリとする
+fn supplementary_ranges(
+    defs: &[(u32, u32, Rc<CharId>)],
+    dft: &Rc<CharId>,
+) -> Vec<(u32, u32, Rc<CharId>)> {
+    let mut bounds = vec![BMP_SIZE as u32, 0x110000];
+    for (beg, end, _) in defs {
+        bounds.push(*beg);
+        bounds.push(end + 1);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut ranges: Vec<(u32, u32, Rc<CharId>)> = Vec::new();
+    for w in bounds.windows(2) {
+        let (beg, end) = (w[0], w[1] - 1);
+        let ch = defs
+            .iter()
+            .rev()
+            .find(|(b, e, _)| *b <= beg && end <= *e)
+            .map_or(dft, |(_, _, ch)| ch);
+        match ranges.last_mut() {
+            Some(last) if last.2.id == ch.id && last.2.mask == ch.mask => last.1 = end,
+            _ => ranges.push((beg, end, ch.clone())),
+        }
     }
+    ranges
 }
 
 impl cmp::Ord for Category {
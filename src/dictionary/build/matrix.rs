@@ -1,10 +1,11 @@
 use std::path::Path;
 use std::io::BufWriter;
 use std::io::Write;
-use std::fs::File;
-use byteorder::{WriteBytesExt, NativeEndian as NE};
+use std::fs::{self, File};
+use byteorder::{WriteBytesExt, LittleEndian as LE};
 use crate::util::*;
 use crate::dictionary::build::*;
+use crate::storage::DicWriter;
 use log::debug;
 
 
@@ -15,7 +16,15 @@ use log::debug;
 pub fn build(input_dir: &Path, output_dir: &Path) -> AppResult<()> {
     let input_file = input_dir.join("matrix.def");
     debug!("input_file: {}", input_file.display());
-    let mut rl = ReadLine::new(input_file.as_path(), "UTF-8")?;
+    let data = build_matrix(&DicSource::File(input_file))?;
+    Ok(fs::write(output_dir.join("matrix.bin"), data)?)
+}
+
+/// 形態素の連接コスト表のバイナリデータを作成する
+/// # Arguments
+/// * `source` - 連接コスト表(`matrix.def`)
+pub(crate) fn build_matrix(source: &DicSource) -> AppResult<Vec<u8>> {
+    let mut rl = source.open("UTF-8")?;
 
     // 一行目はサイズ: [左文脈IDの数] [右文脈IDの数]
     let mut s = String::new();
@@ -25,10 +34,10 @@ pub fn build(input_dir: &Path, output_dir: &Path) -> AppResult<()> {
         .ok_or_else(|| rl.parse_error("Parse error"))?;
     let right_num: i32 = fields.next().and_then(|s| s.parse().ok())
         .ok_or_else(|| rl.parse_error("Parse error"))?;
-    let mut writer = BufWriter::new(File::create(output_dir.join("matrix.bin").as_path())?);
+    let mut writer = DicWriter::new();
 
-    writer.write_i32::<NE>(left_num)?;
-    writer.write_i32::<NE>(right_num)?;
+    writer.write_i32::<LE>(left_num)?;
+    writer.write_i32::<LE>(right_num)?;
 
     // 二行目以降はデータ: [左文脈ID] [右文脈ID] [連接コスト]
     let mut tmp_matrix = vec![0i16; (left_num * right_num) as usize];
@@ -65,11 +74,12 @@ pub fn build(input_dir: &Path, output_dir: &Path) -> AppResult<()> {
             tmp_matrix[(j * left_num + i) as usize] = cost;
         }
     }
+    writer.align::<i16>();
     for cost in tmp_matrix {
-        writer.write_i16::<NE>(cost)?;
+        writer.write_i16::<LE>(cost)?;
     }
 
-    Ok(writer.flush()?)
+    Ok(writer.into_bytes())
 }
 
 // 添付用に簡略化したmatrix.defを元の書式に復元する
@@ -86,7 +96,7 @@ pub fn convert_sparse2dense(input_file: &Path, output_file: &Path, default_cost:
         .ok_or_else(|| rl.parse_error("Parse error"))?;
 
     let mut writer = BufWriter::new(File::create(output_file)?);
-    write!(writer, "{} {}\n", left_num, right_num)?;
+    writeln!(writer, "{} {}", left_num, right_num)?;
 
     // 二行目以降はデータ: [左文脈ID] [右文脈ID] [連接コスト]
     let mut s = String::new();
@@ -108,7 +118,7 @@ pub fn convert_sparse2dense(input_file: &Path, output_file: &Path, default_cost:
             .ok_or_else(|| rl.parse_error("Parse short integer failed."))?;
 
         while i < left_id || j < right_id {
-            write!(writer, "{} {} {}\n", i, j, default_cost)?;
+            writeln!(writer, "{} {} {}", i, j, default_cost)?;
             if j < (right_num - 1) {
                 j += 1;
             } else {
@@ -117,7 +127,7 @@ pub fn convert_sparse2dense(input_file: &Path, output_file: &Path, default_cost:
             }
         }
 
-        write!(writer, "{} {} {}\n", left_id, right_id, cost)?;
+        writeln!(writer, "{} {} {}", left_id, right_id, cost)?;
         if j < (right_num - 1) {
             j += 1;
         } else {
@@ -127,7 +137,7 @@ pub fn convert_sparse2dense(input_file: &Path, output_file: &Path, default_cost:
     }
 
     while i < left_num && j < right_num {
-        write!(writer, "{} {} {}\n", i, j, default_cost)?;
+        writeln!(writer, "{} {} {}", i, j, default_cost)?;
         if j < (right_num - 1) {
             j += 1;
         } else {
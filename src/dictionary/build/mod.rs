@@ -3,7 +3,6 @@ use std::borrow::Cow;
 use std::convert::From;
 use std::error;
 use std::fmt;
-use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
@@ -11,11 +10,15 @@ use std::time::Instant;
 use glob;
 use log::info;
 
+pub use self::builder::*;
 pub use self::charcategory::*;
+pub use self::validate::*;
 pub use self::worddic::*;
 
+mod builder;
 mod charcategory;
 pub mod matrix;
+mod validate;
 mod worddic;
 
 #[derive(Debug)]
@@ -66,7 +69,7 @@ impl From<String> for AppError {
     }
 }
 
-impl<'a> From<&'a str> for AppError {
+impl From<&str> for AppError {
     fn from(e: &str) -> Self {
         AppError::Message(e.to_string())
     }
@@ -96,12 +99,20 @@ impl From<std::num::ParseIntError> for AppError {
     }
 }
 
+/// MeCab形式のテキスト辞書のディレクトリから、バイナリ辞書を構築してディレクトリに保存する
+///
+/// 進捗はログに出力する. 進捗を受け取る場合や、入力や出力先を細かく指定する場合は`DictionaryBuilder`を使用する
+/// # Arguments
+/// * `input_dir`  - テキスト辞書があるディレクトリ
+/// * `output_dir` - バイナリ辞書の保存先ディレクトリ
+/// * `delimiter`  - 単語辞書内の各項目の区切り文字
+/// * `encoding`   - テキスト辞書の文字列エンコーディング
 pub fn build_dic(
     input_dir: &Path,
     output_dir: &Path,
     delimiter: String,
     encoding: &str,
-) -> AppResult<i32> {
+) -> AppResult<()> {
     info!(
         "output_dir: {}, input_dir: {}, delimiter: {:?}, encoding: {}",
         output_dir.display(),
@@ -109,27 +120,15 @@ pub fn build_dic(
         delimiter,
         encoding
     );
-    fs::create_dir_all(&output_dir).expect("couldn't create directory");
 
     let start_time = Instant::now();
-    let mut wd = WordDic::new(input_dir, encoding, output_dir, delimiter);
-    let cc = CharCategory::new(input_dir, encoding, output_dir);
-    println!("### Build word trie");
-    wd.build_word_id_map()?;
-
-    println!("### Build word dictionary");
-    wd.build_word_info()?;
-
-    println!("### Build matrix");
-    matrix::build(input_dir, output_dir)?;
-
-    println!("### Build char-category dictionary");
-    cc.build()?;
+    let mut builder = DictionaryBuilder::from_dir(input_dir, encoding)?;
+    builder.set_delimiter(&delimiter);
+    builder.build_to_dir(output_dir)?;
 
     let elapsed = start_time.elapsed();
     let ms =
         ((elapsed.as_secs() as f64) * 1000.0) + ((elapsed.subsec_nanos() as f64) / 1_000_000.0);
-    println!("DONE");
-    println!("elapsed: {} ms", ms);
-    Ok(0)
+    info!("elapsed: {} ms", ms);
+    Ok(())
 }
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dictionary::build::DicSource;
+use crate::feature::split_feature;
+use crate::util::ReadLine;
+
+const CRLF: &[char] = &['\r', '\n'];
+
+/// 検査で見つかった問題の重大度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// 辞書を構築できないか、構築した辞書で正しく解析できない
+    Error,
+    /// 辞書は構築できるが、意図しない内容になっている可能性がある
+    Warning,
+}
+
+/// 検査で見つかった問題
+#[derive(Debug, Clone)]
+pub struct ValidationIssue {
+    /// 重大度
+    pub severity: Severity,
+    /// 問題の内容
+    pub message: String,
+    /// 問題のあるファイルのパス(または名前)
+    pub path: PathBuf,
+    /// 問題のある行の行番号(1始まり). 特定の行に関係しない場合は0
+    pub line_number: i32,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}: {}\t{{file: {}, line: {}}}",
+            severity,
+            self.message,
+            self.path.display(),
+            self.line_number
+        )
+    }
+}
+
+/// テキスト辞書の検査結果
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// 見つかった問題. ファイルのパスと行番号の順に並ぶ
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// 辞書の構築を妨げる問題があるかどうか
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// 重大度が`Error`の問題
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    /// 重大度が`Warning`の問題
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+
+    fn push(&mut self, severity: Severity, path: &Path, line_number: i32, message: String) {
+        self.issues.push(ValidationIssue {
+            severity,
+            message,
+            path: path.to_path_buf(),
+            line_number,
+        });
+    }
+
+    fn push_at(&mut self, severity: Severity, rl: &ReadLine, message: String) {
+        self.push(severity, rl.path(), rl.line_number(), message);
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// 行の出現位置
+type Location = (PathBuf, i32);
+
+/// 単語辞書と未知語の定義の、一つのファイルの種類についての集計
+#[derive(Default)]
+struct EntryStats {
+    /// 素性の項目数ごとの、その項目数を持つ行の位置
+    feature_columns: HashMap<usize, Vec<Location>>,
+}
+
+impl EntryStats {
+    /// 最も多い項目数と異なる項目数の素性を報告する
+    fn report_inconsistent_columns(&self, report: &mut ValidationReport) {
+        let majority = match self
+            .feature_columns
+            .iter()
+            .max_by_key(|(columns, locations)| (locations.len(), std::cmp::Reverse(**columns)))
+        {
+            Some((&columns, _)) => columns,
+            None => return,
+        };
+        let mut others: Vec<(&usize, &Location)> = self
+            .feature_columns
+            .iter()
+            .filter(|(&columns, _)| columns != majority)
+            .flat_map(|(columns, locations)| locations.iter().map(move |l| (columns, l)))
+            .collect();
+        others.sort_by(|a, b| a.1.cmp(b.1));
+        for (columns, (path, line_number)) in others {
+            report.push(
+                Severity::Warning,
+                path,
+                *line_number,
+                format!(
+                    "Feature has {} fields, but most entries have {}.",
+                    columns, majority
+                ),
+            );
+        }
+    }
+}
+
+/// 単語辞書の一行の検査結果
+struct Entry<'a> {
+    surface: &'a str,
+    left_id: i16,
+    right_id: i16,
+    feature: &'a str,
+}
+
+/// テキスト辞書の入力を検査する
+pub(crate) struct Validator<'a> {
+    delim: &'a str,
+    /// 連接コスト表の大きさ(左文脈IDの数, 右文脈IDの数)
+    matrix_size: Option<(i32, i32)>,
+    report: ValidationReport,
+}
+
+impl<'a> Validator<'a> {
+    pub(crate) fn new(delim: &'a str) -> Validator<'a> {
+        Validator {
+            delim,
+            matrix_size: None,
+            report: ValidationReport::default(),
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> ValidationReport {
+        self.report
+            .issues
+            .sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+        self.report
+    }
+
+    /// 読み込み元を開く. 開けない場合は問題として報告して`None`を返す
+    fn open<'s>(&mut self, source: &'s DicSource, encoding: &str) -> Option<ReadLine<'s>> {
+        match source.open(encoding) {
+            Ok(rl) => Some(rl),
+            Err(e) => {
+                self.report
+                    .push(Severity::Error, source.name(), 0, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// 一行読み込む. ファイルの終わりか、読み込めない場合は`false`を返す
+    fn next_line(&mut self, rl: &mut ReadLine, s: &mut String) -> bool {
+        match rl.next(s) {
+            Ok(len) => len > 0,
+            Err(e) => {
+                self.report.push_at(Severity::Error, rl, e.to_string());
+                false
+            }
+        }
+    }
+
+    /// 連接コスト表の大きさを読み込む
+    pub(crate) fn check_matrix(&mut self, source: &DicSource) {
+        let mut rl = match self.open(source, "UTF-8") {
+            Some(rl) => rl,
+            None => return,
+        };
+        let mut s = String::new();
+        if !self.next_line(&mut rl, &mut s) {
+            return;
+        }
+        let mut fields = s.split_whitespace().map(|f| f.parse::<i32>().ok());
+        match (fields.next().flatten(), fields.next().flatten()) {
+            (Some(left_num), Some(right_num)) if left_num >= 0 && right_num >= 0 => {
+                self.matrix_size = Some((left_num, right_num))
+            }
+            _ => self.report.push_at(
+                Severity::Error,
+                &rl,
+                "The first line must be the number of left and right context IDs.".to_string(),
+            ),
+        }
+    }
+
+    /// 未知語の定義を検査する
+    /// # Return
+    /// 定義されているカテゴリ名と、最初に定義された位置
+    pub(crate) fn check_unk_def(
+        &mut self,
+        source: &DicSource,
+        encoding: &str,
+    ) -> Vec<(String, Location)> {
+        let mut categories: Vec<(String, Location)> = Vec::new();
+        let mut stats = EntryStats::default();
+        self.check_entries(source, encoding, &mut stats, &mut |rl, entry| {
+            if !categories.iter().any(|(name, _)| name == entry.surface) {
+                let location = (rl.path().to_path_buf(), rl.line_number());
+                categories.push((entry.surface.to_string(), location));
+            }
+        });
+        stats.report_inconsistent_columns(&mut self.report);
+        categories
+    }
+
+    /// 単語辞書を検査する
+    pub(crate) fn check_words(&mut self, sources: &[(DicSource, String)]) {
+        let mut stats = EntryStats::default();
+        let mut entries: HashMap<String, Location> = HashMap::new();
+        let mut duplicates: Vec<(Location, Location)> = Vec::new();
+        for (source, encoding) in sources {
+            self.check_entries(source, encoding, &mut stats, &mut |rl, entry| {
+                let key = format!(
+                    "{}\0{}\0{}\0{}",
+                    entry.surface, entry.left_id, entry.right_id, entry.feature
+                );
+                let location = (rl.path().to_path_buf(), rl.line_number());
+                match entries.get(&key) {
+                    Some(first) => duplicates.push((location, first.clone())),
+                    None => {
+                        entries.insert(key, location);
+                    }
+                }
+            });
+        }
+        for ((path, line_number), (first_path, first_line)) in duplicates {
+            self.report.push(
+                Severity::Warning,
+                &path,
+                line_number,
+                format!(
+                    "Duplicated entry (first defined at {}:{}).",
+                    first_path.display(),
+                    first_line
+                ),
+            );
+        }
+        stats.report_inconsistent_columns(&mut self.report);
+    }
+
+    /// 文字カテゴリの定義を検査し、未知語の定義との整合性を調べる
+    /// # Arguments
+    /// * `unk_categories` - 未知語の定義に含まれるカテゴリ名
+    pub(crate) fn check_char_def(
+        &mut self,
+        source: &DicSource,
+        encoding: &str,
+        unk_categories: &[(String, Location)],
+    ) {
+        let mut rl = match self.open(source, encoding) {
+            Some(rl) => rl,
+            None => return,
+        };
+        let mut categories: Vec<(String, i32)> = Vec::new();
+        let mut s = String::new();
+        while self.next_line(&mut rl, &mut s) {
+            let line = s.trim_end();
+            let line = match line.find('#') {
+                Some(p) => line[..p].trim_end(),
+                None => line,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let first = fields.next().unwrap();
+            if first.starts_with('0') {
+                // 文字とカテゴリのマッピング
+                for name in fields {
+                    if !categories.iter().any(|(c, _)| c == name) {
+                        self.report.push_at(
+                            Severity::Error,
+                            &rl,
+                            format!("Category '{}' is undefined.", name),
+                        );
+                    }
+                }
+                continue;
+            }
+            let rest: Vec<&str> = fields.collect();
+            if rest.len() < 3
+                || !rest[..2].iter().all(|f| *f == "0" || *f == "1")
+                || rest[2].parse::<u32>().is_err()
+            {
+                self.report.push_at(
+                    Severity::Error,
+                    &rl,
+                    "Invalid char category definition.".to_string(),
+                );
+            }
+            categories.push((first.to_string(), rl.line_number()));
+        }
+
+        for mandatory in &["DEFAULT", "SPACE"] {
+            if !categories.iter().any(|(name, _)| name == mandatory) {
+                self.report.push(
+                    Severity::Error,
+                    rl.path(),
+                    0,
+                    format!("Missing mandatory category '{}'.", mandatory),
+                );
+            }
+        }
+        for (name, line_number) in &categories {
+            if !unk_categories.iter().any(|(c, _)| c == name) {
+                self.report.push(
+                    Severity::Error,
+                    rl.path(),
+                    *line_number,
+                    format!("Category '{}' has no entry in unk.def.", name),
+                );
+            }
+        }
+        for (name, (path, line_number)) in unk_categories {
+            if !categories.iter().any(|(c, _)| c == name) {
+                self.report.push(
+                    Severity::Warning,
+                    path,
+                    *line_number,
+                    format!(
+                        "Category '{}' is not defined in char.def, so its entries are never used.",
+                        name
+                    ),
+                );
+            }
+        }
+    }
+
+    /// 単語辞書と同じ書式のファイルの各行を検査する
+    /// # Arguments
+    /// * `callback` - 書式に誤りの無い行ごとに呼ばれる
+    fn check_entries(
+        &mut self,
+        source: &DicSource,
+        encoding: &str,
+        stats: &mut EntryStats,
+        callback: &mut dyn FnMut(&ReadLine, &Entry),
+    ) {
+        let mut rl = match self.open(source, encoding) {
+            Some(rl) => rl,
+            None => return,
+        };
+        let mut s = String::new();
+        while self.next_line(&mut rl, &mut s) {
+            let line = s.trim_end_matches(CRLF);
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(entry) = self.check_entry(&rl, line) {
+                let columns = if self.delim == "," {
+                    split_feature(entry.feature).count()
+                } else {
+                    entry.feature.split(self.delim).count()
+                };
+                stats
+                    .feature_columns
+                    .entry(columns)
+                    .or_default()
+                    .push((rl.path().to_path_buf(), rl.line_number()));
+                callback(&rl, &entry);
+            }
+        }
+    }
+
+    /// 単語辞書の一行を検査する. 書式に誤りがある場合は問題として報告して`None`を返す
+    fn check_entry<'l>(&mut self, rl: &ReadLine, line: &'l str) -> Option<Entry<'l>> {
+        let fields: Vec<&str> = line.splitn(5, self.delim).collect();
+        if fields.len() < 5 {
+            self.report.push_at(
+                Severity::Error,
+                rl,
+                format!("Too few fields (expected surface, left id, right id, cost and feature separated by '{}').", self.delim),
+            );
+            return None;
+        }
+        if fields[0].is_empty() {
+            self.report
+                .push_at(Severity::Error, rl, "Empty Word surface.".to_string());
+        }
+
+        let mut parse_id = |name: &str, s: &str| -> Option<i16> {
+            let id = s.parse::<i16>().ok().filter(|id| *id >= 0);
+            if id.is_none() {
+                self.report.push_at(
+                    Severity::Error,
+                    rl,
+                    format!("Invalid {} context ID '{}'.", name, s),
+                );
+            }
+            id
+        };
+        let left_id = parse_id("left", fields[1]);
+        let right_id = parse_id("right", fields[2]);
+
+        match fields[3].parse::<i64>() {
+            Ok(cost) if cost < i64::from(i16::MIN) || cost > i64::from(i16::MAX) => {
+                self.report
+                    .push_at(Severity::Error, rl, format!("Cost {} overflows i16.", cost));
+            }
+            Ok(_) => (),
+            Err(_) => self.report.push_at(
+                Severity::Error,
+                rl,
+                format!("Invalid cost '{}'.", fields[3]),
+            ),
+        }
+
+        let (left_id, right_id) = (left_id?, right_id?);
+        if let Some((left_num, right_num)) = self.matrix_size {
+            // 連接コスト表は 前の形態素の右文脈ID × 後の形態素の左文脈ID で引く
+            if i32::from(right_id) >= left_num || i32::from(left_id) >= right_num {
+                self.report.push_at(
+                    Severity::Error,
+                    rl,
+                    format!(
+                        "Context ID out of range for matrix.def ({} x {}): left_id: {}, right_id: {}",
+                        left_num, right_num, left_id, right_id
+                    ),
+                );
+            }
+        }
+
+        Some(Entry {
+            surface: fields[0],
+            left_id,
+            right_id,
+            feature: fields[4],
+        })
+    }
+}
@@ -1,21 +1,20 @@
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
-use std::io::{BufWriter, Write};
-use std::fs::File;
-use byteorder::{WriteBytesExt, NativeEndian as NE};
+use std::io::Write;
+use byteorder::{WriteBytesExt, LittleEndian as LE};
 use std::cmp;
-use glob::glob;
 use crate::util::*;
-use crate::Utf16String;
-use crate::dictionary::build::charcategory;
+use crate::storage::{DicFile, DicWriter};
 use crate::dictionary::build::*;
 use crate::trie::{builder, Searcher};
-use log::debug;
 
 
 const CRLF: &[char] = &['\r', '\n'];
 
 /// テキスト単語辞書をパースして、バイナリ単語辞書を構築する
+///
+/// 段階ごとに出力先ディレクトリへ保存する. 辞書全体を構築する場合は`DictionaryBuilder`を使用する
 pub struct WordDic {
     input_dir: PathBuf,
     encoding: String,
@@ -30,6 +29,7 @@ impl WordDic {
     /// * `encoding`   - テキスト単語辞書の文字列エンコーディング
     /// * `output_dir` - バイナリ単語辞書の保存先ディレクトリ
     /// * `delim`      - 単語辞書内の各項目の区切り文字
+    #[deprecated(note = "use `DictionaryBuilder` to build the whole dictionary")]
     pub fn new(input_dir: &Path, encoding: &str, output_dir: &Path, delim: String) -> WordDic {
         WordDic {
             input_dir: input_dir.to_owned(),
@@ -41,209 +41,242 @@ impl WordDic {
 
     /// 単語の表層形をキーに、対応する一意なIDを値としたtrieを作成し、保存する
     pub fn build_word_id_map(&mut self) -> AppResult<()> {
-        let mut key_list: Vec<String> = Vec::new();
-
-        // 未知語定義からキーを集める
-        self.collect_key(&self.input_dir.join("unk.def"), &mut key_list, charcategory::KEY_PREFIX)?;
-
-        // 単語辞書からキーを集める
-        let paths = glob(self.input_dir.join("*.csv").to_str().unwrap())?;
-        for entry in paths {
-            match entry {
-                Ok(ref csv_file) if csv_file.is_file() =>
-                    self.collect_key(csv_file, &mut key_list, "")?,
-                _ => (),
-            }
-        }
+        let builder = DictionaryBuilder::from_dir(&self.input_dir, &self.encoding)?;
+        let word2id = build_word_trie(&builder.word_sources()?, &self.delim)?;
+        Ok(fs::write(self.output_dir.join("word2id"), word2id)?)
+    }
 
-        builder::build(key_list, self.output_dir.join("word2id").as_path())?;
+    /// バイナリ単語辞書を作成し、保存する。
+    pub fn build_word_info(&mut self) -> AppResult<()> {
+        let builder = DictionaryBuilder::from_dir(&self.input_dir, &self.encoding)?;
+        let wid = DicFile::from_bytes("word2id", fs::read(self.output_dir.join("word2id"))?)?;
+        let wid = Searcher::from_dic_file(wid)?;
+        for (name, data) in build_word_info(&builder.word_sources()?, &self.delim, &wid, false)? {
+            fs::write(self.output_dir.join(name), data)?;
+        }
         Ok(())
     }
+}
 
-    fn collect_key(&self, path: &Path, key_list: &mut Vec<String>, prefix: &str) -> AppResult<()> {
-        debug!("path: {}", path.display());
-        let mut rl = ReadLine::new(path, &self.encoding)?;
-        let mut s = String::new();
-
-        loop {
-            let len = rl.next(&mut s)?;
-            if len < 1 {
-                break;
-            }
-            let idx = s.find(&self.delim)
-                .ok_or_else(|| rl.parse_error(format!(
-                    "Word surface must be terminated with '{}'.", self.delim)))?;
-            let key = &s[0..idx];
-            //println!("{}: {}, [{}]", line_num, idx, key);
-            key_list.push(prefix.to_string() + key);
-        }
+/// 単語辞書の読み込み元
+pub(crate) struct WordSource<'a> {
+    pub(crate) source: &'a DicSource,
+    pub(crate) encoding: &'a str,
+    /// trieのキーとして、表層形の前に付加する文字列
+    pub(crate) prefix: &'static str
+}
 
-        Ok(())
+/// 単語の表層形をキーに、対応する一意なIDを値としたtrieを作成する
+/// # Arguments
+/// * `sources` - 単語辞書の読み込み元のリスト
+/// * `delim`   - 単語辞書内の各項目の区切り文字
+pub(crate) fn build_word_trie(sources: &[WordSource], delim: &str) -> AppResult<Vec<u8>> {
+    let mut key_list: Vec<String> = Vec::new();
+    for src in sources {
+        collect_key(src, delim, &mut key_list)?;
     }
 
-    /// バイナリ単語辞書を作成し、保存する。
-    pub fn build_word_info(&mut self) -> AppResult<()> {
-        let wid = Searcher::from_path(self.output_dir.join("word2id").as_path())?;
-        let mut ws: Vec<Vec<WordInfo>> = Vec::with_capacity(wid.size());
-        for _ in 0..wid.size() {
-            ws.push(Vec::new());
-        }
+    let mut buf = Vec::new();
+    builder::build_to_writer(key_list, &mut buf)?;
+    Ok(buf)
+}
 
-        // 未知語定義からデータを集める
-        self.collect_word_info(self.input_dir.join("unk.def").as_path(), &wid, charcategory::KEY_PREFIX, &mut ws)?;
+fn collect_key(src: &WordSource, delim: &str, key_list: &mut Vec<String>) -> AppResult<()> {
+    let mut rl = src.source.open(src.encoding)?;
+    let mut s = String::new();
 
-        // 単語辞書からデータを集める
-        let paths = glob(self.input_dir.join("*.csv").to_str().unwrap())?;
-        for entry in paths {
-            match entry {
-                Ok(ref csv_file) if csv_file.is_file() =>
-                    self.collect_word_info(csv_file, &wid, "", &mut ws)?,
-                _ => (),
-            }
+    loop {
+        let len = rl.next(&mut s)?;
+        if len < 1 {
+            break;
         }
+        let idx = s.find(delim)
+            .ok_or_else(|| rl.parse_error(format!(
+                "Word surface must be terminated with '{}'.", delim)))?;
+        let key = &s[0..idx];
+        key_list.push(src.prefix.to_string() + key);
+    }
 
+    Ok(())
+}
+
+/// バイナリ単語辞書を作成する
+/// # Arguments
+/// * `sources` - 単語辞書の読み込み元のリスト. `build_word_trie`に渡したものと同じでなければならない
+/// * `delim`   - 単語辞書内の各項目の区切り文字
+/// * `wid`     - `build_word_trie`で作成したtrie
+/// * `keep_homographs` - 表層形と文脈IDが同じで、コストの大きい単語も残すかどうか
+/// # Return
+/// ファイル名と、ファイルの内容の組のリスト
+pub(crate) fn build_word_info(sources: &[WordSource], delim: &str, wid: &Searcher, keep_homographs: bool)
+    -> AppResult<Vec<(&'static str, Vec<u8>)>> {
+    let mut ws: Vec<Vec<WordInfo>> = Vec::with_capacity(wid.size());
+    for _ in 0..wid.size() {
+        ws.push(Vec::new());
+    }
+
+    // 単語辞書からデータを集める
+    for src in sources {
+        collect_word_info(src, delim, wid, &mut ws)?;
+    }
+
+    if keep_homographs {
+        // 同じ文脈IDの単語はコストの小さい順に、同じコストの場合は定義された順に並べる
+        for wlist in &mut ws {
+            wlist.sort();
+        }
+    } else {
         // 無駄な項目を削除する
-        self.remove_unused_entry(&mut ws);
-
-        // 単語情報を出力
-        let mut wdat: Utf16String = Vec::new();
-        {
-            let mut writer = BufWriter::new(File::create(self.output_dir.join("word.inf").as_path())?);
-
-            for wlist in &ws {
-                // dataOffset
-                for w in wlist {
-                    writer.write_i32::<NE>(wdat.len() as i32)?;
-                    wdat.extend_from_slice(&w.data);
-                }
-            }
-            writer.write_i32::<NE>(wdat.len() as i32)?;
+        remove_unused_entry(&mut ws);
+    }
 
-            for wlist in &ws {
-                // leftId
-                for w in wlist {
-                    writer.write_i16::<NE>(w.left_id)?;
-                }
-            }
-            writer.write_i16::<NE>(0)?;
+    let mut files = Vec::with_capacity(3);
 
-            for wlist in &ws {
-                // rightId
-                for w in wlist {
-                    writer.write_i16::<NE>(w.right_id)?;
-                }
-            }
-            writer.write_i16::<NE>(0)?;
+    // 単語情報を出力
+    let mut wdat = String::new();
+    {
+        let mut writer = DicWriter::new();
 
-            for wlist in &ws {
-                // cost
-                for w in wlist {
-                    writer.write_i16::<NE>(w.cost)?;
-                }
+        for wlist in &ws {
+            // dataOffset
+            for w in wlist {
+                writer.write_i32::<LE>(wdat.len() as i32)?;
+                wdat.push_str(&w.data);
             }
-            writer.write_i16::<NE>(0)?;
-            writer.flush()?
-        }
-        {
-            // 単語データを出力
-            let mut writer = BufWriter::new(File::create(self.output_dir.join("word.dat").as_path())?);
-            writer.put_string(&wdat)?;
-            writer.flush()?
         }
-        {
-            // 単語情報の配列へのインデックスを保存する
-            let mut writer = BufWriter::new(File::create(self.output_dir.join("word.ary.idx").as_path())?);
-            let mut beg_index = 0i32;
-            for wlist in &ws {
-                writer.write_i32::<NE>(beg_index)?;
-                beg_index += wlist.len() as i32;
+        writer.write_i32::<LE>(wdat.len() as i32)?;
+
+        writer.align::<i16>();
+        for wlist in &ws {
+            // leftId
+            for w in wlist {
+                writer.write_i16::<LE>(w.left_id)?;
             }
-            writer.write_i32::<NE>(beg_index)?;
-            writer.flush()?
         }
+        writer.write_i16::<LE>(0)?;
 
-        Ok(())
-    }
-
-    fn collect_word_info(&self, path: &Path, wid: &Searcher, prefix: &str, ws: &mut Vec<Vec<WordInfo>>) -> AppResult<()> {
-        debug!("path: {}", path.display());
-        let mut rl = ReadLine::new(path, &self.encoding)?;
-        let mut s = String::new();
-
-        loop {
-            let len = rl.next(&mut s)?;
-            if len < 1 {
-                break;
+        for wlist in &ws {
+            // rightId
+            for w in wlist {
+                writer.write_i16::<LE>(w.right_id)?;
             }
-            let s = s.trim_end_matches(CRLF);
+        }
+        writer.write_i16::<LE>(0)?;
 
-            // key
-            let p1 = s.find(&self.delim).ok_or_else(||
-                rl.parse_error(format!("Word surface must be terminated with '{}'.", self.delim)))?;
-            if p1 == 0 {
-                return Err(rl.parse_error("Empty Word surface."));
-            }
-            let mut start = p1 + 1;
-            // left id
-            let p2 = start + (&s[start..]).find(&self.delim).ok_or_else(||
-                rl.parse_error(format!("Word left context id must be terminated with '{}'.", self.delim)))?;
-            start = p2 + 1;
-            // right id
-            let p3 = start + (&s[start..]).find(&self.delim).ok_or_else(||
-                rl.parse_error(format!("Word right context id must be terminated with '{}'.", self.delim)))?;
-            start = p3 + 1;
+        for wlist in &ws {
             // cost
-            let p4 = start + (&s[start..]).find(&self.delim).ok_or_else(||
-                rl.parse_error(format!("Word cost must be terminated with '{}'.", self.delim)))?;
-            start = p4 + 1;
+            for w in wlist {
+                writer.write_i16::<LE>(w.cost)?;
+            }
+        }
+        writer.write_i16::<LE>(0)?;
+        files.push(("word.inf", writer.into_bytes()));
+    }
+    {
+        // 単語データを出力
+        let mut writer = DicWriter::new();
+        writer.write_all(wdat.as_bytes())?;
+        files.push(("word.dat", writer.into_bytes()));
+    }
+    {
+        // 単語情報の配列へのインデックスを保存する
+        let mut writer = DicWriter::new();
+        let mut beg_index = 0i32;
+        for wlist in &ws {
+            writer.write_i32::<LE>(beg_index)?;
+            beg_index += wlist.len() as i32;
+        }
+        writer.write_i32::<LE>(beg_index)?;
+        files.push(("word.ary.idx", writer.into_bytes()));
+    }
 
-            let data = &s[start..]; // data
+    Ok(files)
+}
 
-//            debug!("p1: {}, p2: {}, p3: {}, p4: {}, s: '{}'", p1, p2, p3, p4, s);
-//            debug!("left_id: {}, right_id: {}, cost: {}, data: '{}', key: '{}'",
-//                   &s[(p1 + 1)..p2], &s[(p2 + 1)..p3], &s[(p3 + 1)..p4], data, &s[0..p1]);
+fn collect_word_info(src: &WordSource, delim: &str, wid: &Searcher, ws: &mut [Vec<WordInfo>]) -> AppResult<()> {
+    let mut rl = src.source.open(src.encoding)?;
+    let mut s = String::new();
 
-            let key_utf16 = (prefix.to_string() + &s[0..p1]).encode_utf16().collect::<Vec<_>>();
-            let id = wid.search(&key_utf16);
-            if id < 0 {
-                Err(rl.parse_error(format!("Word '{}' is unregistered in trie", &s[0..p1])))?;
-            }
+    loop {
+        let len = rl.next(&mut s)?;
+        if len < 1 {
+            break;
+        }
+        let (surface, word_info) = parse_word_entry(&s, delim, &rl)?;
 
-            ws[id as usize].push(WordInfo {
-                left_id: (&s[(p1 + 1)..p2]).parse()?,
-                right_id: (&s[(p2 + 1)..p3]).parse()?,
-                cost: (&s[(p3 + 1)..p4]).parse()?,
-                // UTF-16に変換する
-                data: data.encode_utf16().collect::<Vec<_>>()
-            });
+        let id = wid.search(&(src.prefix.to_string() + surface));
+        if id < 0 {
+            Err(rl.parse_error(format!("Word '{}' is unregistered in trie", surface)))?;
         }
 
-        Ok(())
+        ws[id as usize].push(word_info);
     }
 
-    /// 単語辞書から無駄な項目を除外する
-    /// 参照: http://d.hatena.ne.jp/sile/20100227/1267260585
-    fn remove_unused_entry(&self, ws: &mut Vec<Vec<WordInfo>>) {
-        for wlist in ws {
-            wlist.sort();
-            let mut last = 0usize;
-            for i in 1..wlist.len() {
-                if !(wlist[last].left_id == wlist[i].left_id &&
-                    wlist[last].right_id == wlist[i].right_id) {
-                    last += 1;
-                    wlist[last] = wlist[i].clone();
-                }
+    Ok(())
+}
+
+/// 単語辞書から無駄な項目を除外する
+/// 参照: http://d.hatena.ne.jp/sile/20100227/1267260585
+fn remove_unused_entry(ws: &mut Vec<Vec<WordInfo>>) {
+    for wlist in ws {
+        wlist.sort();
+        let mut last = 0usize;
+        for i in 1..wlist.len() {
+            if !(wlist[last].left_id == wlist[i].left_id &&
+                wlist[last].right_id == wlist[i].right_id) {
+                last += 1;
+                wlist[last] = wlist[i].clone();
             }
-            wlist.truncate(last + 1);
         }
+        wlist.truncate(last + 1);
+    }
+}
+
+/// テキスト単語辞書の一行をパースする
+/// # Arguments
+/// * `line`  - 単語辞書の行. 末尾の改行文字は無視される
+/// * `delim` - 単語辞書内の各項目の区切り文字
+/// * `rl`    - エラーの報告に使用する、行の読み込み元
+/// # Return
+/// 単語の表層形と、単語情報の組
+pub(crate) fn parse_word_entry<'a>(line: &'a str, delim: &str, rl: &ReadLine) -> AppResult<(&'a str, WordInfo)> {
+    let s = line.trim_end_matches(CRLF);
+
+    // key
+    let p1 = s.find(delim).ok_or_else(||
+        rl.parse_error(format!("Word surface must be terminated with '{}'.", delim)))?;
+    if p1 == 0 {
+        return Err(rl.parse_error("Empty Word surface."));
     }
+    let mut start = p1 + 1;
+    // left id
+    let p2 = start + s[start..].find(delim).ok_or_else(||
+        rl.parse_error(format!("Word left context id must be terminated with '{}'.", delim)))?;
+    start = p2 + 1;
+    // right id
+    let p3 = start + s[start..].find(delim).ok_or_else(||
+        rl.parse_error(format!("Word right context id must be terminated with '{}'.", delim)))?;
+    start = p3 + 1;
+    // cost
+    let p4 = start + s[start..].find(delim).ok_or_else(||
+        rl.parse_error(format!("Word cost must be terminated with '{}'.", delim)))?;
+    start = p4 + 1;
+
+    let data = &s[start..]; // data
+
+    Ok((&s[0..p1], WordInfo {
+        left_id: s[(p1 + 1)..p2].parse()?,
+        right_id: s[(p2 + 1)..p3].parse()?,
+        cost: s[(p3 + 1)..p4].parse()?,
+        data: data.to_string()
+    }))
 }
 
-struct WordInfo {
-    left_id: i16,
-    right_id: i16,
-    cost: i16,
-    data: Utf16String
+pub(crate) struct WordInfo {
+    pub(crate) left_id: i16,
+    pub(crate) right_id: i16,
+    pub(crate) cost: i16,
+    pub(crate) data: String
 }
 
 impl cmp::Ord for WordInfo {
@@ -1,9 +1,14 @@
-use std::io::{self, BufReader};
+use std::io;
 
+use std::cmp::Ordering;
+
+use crate::storage::Array;
 use crate::util::*;
-use crate::Utf16Char;
 
-pub const SPACE_CHAR: Utf16Char = 0x0020u16;
+pub const SPACE_CHAR: char = ' ';
+
+/// 文字ごとに文字カテゴリを保持する範囲(基本多言語面)の文字数
+pub const BMP_SIZE: usize = 0x10000;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Category {
@@ -16,31 +21,60 @@ pub struct Category {
 #[derive(Debug, Clone)]
 pub struct CharCategory {
     categories: Box<[Category]>,
-    char2id: Box<[i32]>,
-    eql_masks: Box<[i32]>,
+    char2id: Array<i32>,
+    eql_masks: Array<i32>,
+    /// 基本多言語面より上の文字の範囲(開始, 終了, カテゴリID, 互換カテゴリのマスク). 開始位置の昇順
+    supplementary: Box<[(u32, u32, i32, i32)]>,
 }
 
 impl CharCategory {
     pub fn new(dir: &mut dyn DirLike) -> io::Result<CharCategory> {
-        let path = "code2category";
-        let src_len = dir.file_size(path)?;
-        let mut reader = BufReader::new(dir.open(path)?);
+        let mut file = dir.open_dic_file("code2category")?;
+        let char2id = file.read_array(BMP_SIZE)?;
+        let eql_masks = file.read_array(BMP_SIZE)?;
+        // NOTE: 基本多言語面より上の範囲を持たない辞書(以前の書式)では空になる
+        let supplementary = file
+            .read_to_end::<i32>()?
+            .chunks_exact(4)
+            .map(|r| (r[0] as u32, r[1] as u32, r[2], r[3]))
+            .collect();
 
-        let cc = Ok(CharCategory {
+        Ok(CharCategory {
             categories: Self::read_categories(dir)?,
-            char2id: reader.get_int_array((src_len / 4 / 2) as usize)?,
-            eql_masks: reader.get_int_array((src_len / 4 / 2) as usize)?,
-        });
+            char2id,
+            eql_masks,
+            supplementary,
+        })
+    }
 
-        cc
+    /// 文字のカテゴリIDと互換カテゴリのマスクを返す
+    fn lookup(&self, ch: char) -> (i32, i32) {
+        let code = ch as u32;
+        if (code as usize) < BMP_SIZE {
+            return (self.char2id[code as usize], self.eql_masks[code as usize]);
+        }
+        let found = self.supplementary.binary_search_by(|&(beg, end, _, _)| {
+            if end < code {
+                Ordering::Less
+            } else if beg > code {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+        match found {
+            Ok(i) => (self.supplementary[i].2, self.supplementary[i].3),
+            // 範囲を持たない辞書では、以前と同様に上位サロゲートの文字カテゴリを使う
+            Err(_) => (self.char2id[0xD800], self.eql_masks[0xD800]),
+        }
     }
 
-    pub fn is_compatible(&self, code1: Utf16Char, code2: Utf16Char) -> bool {
-        (self.eql_masks[code1 as usize] & self.eql_masks[code2 as usize]) != 0
+    pub fn is_compatible(&self, ch1: char, ch2: char) -> bool {
+        (self.lookup(ch1).1 & self.lookup(ch2).1) != 0
     }
 
     fn read_categories(dir: &mut dyn DirLike) -> io::Result<Box<[Category]>> {
-        let data = read_all_as_int_array(dir, "char.category")?;
+        let data = dir.open_dic_file("char.category")?.read_to_end::<i32>()?;
         let size = data.len() / 4;
         let mut v = Vec::with_capacity(size);
         for i in 0..size {
@@ -54,7 +88,7 @@ impl CharCategory {
         Ok(v.into_boxed_slice())
     }
 
-    pub fn category(&self, code: Utf16Char) -> &Category {
-        &self.categories[self.char2id[code as usize] as usize]
+    pub fn category(&self, ch: char) -> &Category {
+        &self.categories[self.lookup(ch).0 as usize]
     }
 }
@@ -1,26 +1,26 @@
-use std::io::{self, BufReader};
+use std::io;
 
+use crate::storage::Array;
 use crate::util::*;
 
 /// 形態素の連接コスト表を扱う
 #[derive(Clone)]
 pub struct Matrix {
     left_size: i32,
-    #[allow(dead_code)]
     right_size: i32,
-    matrix: Box<[i16]>,
+    matrix: Array<i16>,
 }
 
 impl Matrix {
     pub fn new(dir: &mut dyn DirLike) -> io::Result<Matrix> {
-        let mut reader = BufReader::new(dir.open("matrix.bin")?);
-        let left_size = reader.get_int()?;
-        let right_size = reader.get_int()?;
+        let mut file = dir.open_dic_file("matrix.bin")?;
+        let left_size = file.read_value::<i32>()?;
+        let right_size = file.read_value::<i32>()?;
 
         Ok(Matrix {
             left_size,
             right_size,
-            matrix: reader.get_short_array((left_size * right_size) as usize)?,
+            matrix: file.read_array((left_size * right_size) as usize)?,
         })
     }
 
@@ -28,4 +28,12 @@ impl Matrix {
     pub fn link_cost(&self, left_id: i16, right_id: i16) -> i32 {
         i32::from(self.matrix[(right_id as usize) * (self.left_size as usize) + (left_id as usize)])
     }
+
+    /// 文脈IDの組が連接コスト表の範囲内にあるかどうか
+    pub fn contains(&self, left_id: i16, right_id: i16) -> bool {
+        0 <= left_id
+            && i32::from(left_id) < self.left_size
+            && 0 <= right_id
+            && i32::from(right_id) < self.right_size
+    }
 }
@@ -13,6 +13,9 @@ pub use self::viterbinode::*;
 mod matrix;
 pub use self::matrix::*;
 
+mod userdic;
+pub use self::userdic::*;
+
 pub mod build;
 
 pub trait Callback {
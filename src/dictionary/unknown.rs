@@ -1,7 +1,5 @@
 use crate::dictionary::{self, CharCategory, WordDic, SPACE_CHAR};
 use crate::util::DirLike;
-use crate::Utf16Str;
-use std::cmp::min;
 use std::io::{self};
 
 /// 未知語の検索を行う
@@ -24,35 +22,65 @@ impl Unknown {
 
     pub fn search(
         &self,
-        text: &Utf16Str,
+        text: &str,
         start: usize,
         wdic: &WordDic,
         callback: &mut dyn dictionary::Callback,
     ) {
-        let ch = text[start];
+        let ch = char_at(text, start);
         let ct = self.category.category(ch);
 
         if !callback.is_empty() && !ct.invoke {
             return;
         }
 
+        // NOTE: 長さや同じカテゴリの文字のまとめ上げは文字単位で扱い、位置はバイト単位で扱う
         let is_space = ct.id == self.space_id;
-        let limit = min(text.len(), (ct.length as usize) + start);
-        for i in start..limit {
-            wdic.search_from_trie_id(ct.id, start, (i - start) + 1, is_space, callback);
-            if (i + 1) != limit && !self.category.is_compatible(ch, text[i + 1]) {
+        let mut end = start;
+        for n in 0..ct.length {
+            if end == text.len() {
+                break;
+            }
+            let c = char_at(text, end);
+            if n > 0 && !self.category.is_compatible(ch, c) {
                 return;
             }
+            end += c.len_utf8();
+            wdic.search_from_trie_id(ct.id, start, end - start, is_space, callback);
         }
 
-        if ct.group && limit < text.len() {
-            for i in limit..text.len() {
-                if !self.category.is_compatible(ch, text[i]) {
-                    wdic.search_from_trie_id(ct.id, start, i - start, is_space, callback);
-                    return;
+        if ct.group && end < text.len() {
+            // 長さごとに生成した未知語と同じ長さになる場合は、重複するため生成しない
+            let emitted = end;
+            while end < text.len() {
+                let c = char_at(text, end);
+                if !self.category.is_compatible(ch, c) {
+                    break;
                 }
+                end += c.len_utf8();
+            }
+            if end > emitted {
+                wdic.search_from_trie_id(ct.id, start, end - start, is_space, callback);
             }
-            wdic.search_from_trie_id(ct.id, start, text.len() - start, is_space, callback);
         }
     }
+
+    /// 先頭の文字の文字カテゴリの未知語として、指定した長さの形態素を生成する
+    pub fn search_with_length(
+        &self,
+        text: &str,
+        start: usize,
+        length: usize,
+        wdic: &WordDic,
+        callback: &mut dyn dictionary::Callback,
+    ) {
+        let ct = self.category.category(char_at(text, start));
+        let is_space = ct.id == self.space_id;
+        wdic.search_from_trie_id(ct.id, start, length, is_space, callback);
+    }
+}
+
+/// 指定した位置(バイト単位)の文字を返す
+fn char_at(text: &str, pos: usize) -> char {
+    text[pos..].chars().next().unwrap()
 }
@@ -0,0 +1,179 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use log::debug;
+
+use crate::dictionary::build::{parse_word_entry, AppError, AppResult, DicSource, WordInfo};
+use crate::dictionary::{self, Matrix, ViterbiNode};
+use crate::lattice::NodeKind;
+use crate::trie::{builder, Searcher};
+
+/// 次に作成するユーザー辞書のID. 0はユーザー辞書が無いことを表す
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// 実行時に追加できるユーザー辞書
+///
+/// バイナリ辞書の構築に使用するテキスト単語辞書(`*.csv`)と同じ書式のファイルから作成する
+#[derive(Clone)]
+pub struct UserDic {
+    /// 作成したユーザー辞書ごとに異なるID. 複製した場合は同じIDとなる
+    id: u32,
+    trie: Searcher,
+    data: String,
+    indices: Box<[i32]>,
+
+    /// costs[単語ID] = 単語のコスト
+    costs: Box<[i16]>,
+    /// left_ids[単語ID] = 単語の左文脈ID
+    left_ids: Box<[i16]>,
+    /// right_ids[単語ID] = 単語の右文脈ID
+    right_ids: Box<[i16]>,
+    /// data_offsets[単語ID] = 単語の素性データの開始位置
+    data_offsets: Box<[i32]>,
+}
+
+impl UserDic {
+    /// テキスト単語辞書のファイルを読み込んで、ユーザー辞書を作成する
+    /// # Arguments
+    /// * `path`      - テキスト単語辞書のパス
+    /// * `delimiter` - テキスト単語辞書内の各項目の区切り文字
+    /// * `encoding`  - テキスト単語辞書の文字列エンコーディング
+    pub fn from_csv(path: &Path, delimiter: &str, encoding: &str) -> AppResult<UserDic> {
+        UserDic::from_source(&DicSource::File(path.to_path_buf()), delimiter, encoding)
+    }
+
+    /// テキスト単語辞書を読み込んで、ユーザー辞書を作成する
+    /// # Arguments
+    /// * `source`    - テキスト単語辞書の読み込み元
+    /// * `delimiter` - テキスト単語辞書内の各項目の区切り文字
+    /// * `encoding`  - テキスト単語辞書の文字列エンコーディング
+    pub fn from_source(source: &DicSource, delimiter: &str, encoding: &str) -> AppResult<UserDic> {
+        debug!("source: {}", source.name().display());
+        let mut rl = source.open(encoding)?;
+        let mut entries: Vec<(String, WordInfo)> = Vec::new();
+        let mut s = String::new();
+        loop {
+            let len = rl.next(&mut s)?;
+            if len < 1 {
+                break;
+            }
+            let (surface, word_info) = parse_word_entry(&s, delimiter, &rl)?;
+            entries.push((surface.to_string(), word_info));
+        }
+        if entries.is_empty() {
+            return Err(rl.parse_error("User dictionary has no entries."));
+        }
+
+        let mut trie_bytes: Vec<u8> = Vec::new();
+        builder::build_to_writer(
+            entries.iter().map(|(surface, _)| surface.clone()).collect(),
+            &mut trie_bytes,
+        )?;
+        let trie = Searcher::new(trie_bytes.as_slice())?;
+
+        // trieのIDごとに単語をまとめる
+        let mut ws: Vec<Vec<WordInfo>> = (0..trie.size()).map(|_| Vec::new()).collect();
+        for (surface, word_info) in entries {
+            let id = trie.search(&surface);
+            ws[id as usize].push(word_info);
+        }
+
+        let mut indices = Vec::with_capacity(ws.len() + 1);
+        let mut data = String::new();
+        let mut data_offsets = vec![0];
+        let (mut costs, mut left_ids, mut right_ids) = (Vec::new(), Vec::new(), Vec::new());
+        for wlist in ws {
+            indices.push(costs.len() as i32);
+            for w in wlist {
+                data.push_str(&w.data);
+                data_offsets.push(data.len() as i32);
+                costs.push(w.cost);
+                left_ids.push(w.left_id);
+                right_ids.push(w.right_id);
+            }
+        }
+        indices.push(costs.len() as i32);
+
+        Ok(UserDic {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            trie,
+            data,
+            indices: indices.into_boxed_slice(),
+            costs: costs.into_boxed_slice(),
+            left_ids: left_ids.into_boxed_slice(),
+            right_ids: right_ids.into_boxed_slice(),
+            data_offsets: data_offsets.into_boxed_slice(),
+        })
+    }
+
+    /// 登録されている単語の数
+    pub fn len(&self) -> usize {
+        self.costs.len()
+    }
+
+    /// 登録されている単語が無いかどうか
+    pub fn is_empty(&self) -> bool {
+        self.costs.is_empty()
+    }
+
+    /// ユーザー辞書のID. ラティスのノードがどのユーザー辞書に由来するかの判定に使う
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// 単語の文脈IDが連接コスト表の範囲内にあるかを検査する
+    pub(crate) fn validate(&self, mtx: &Matrix) -> AppResult<()> {
+        for word_id in 0..self.len() {
+            let (left_id, right_id) = (self.left_ids[word_id], self.right_ids[word_id]);
+            if !mtx.contains(right_id, left_id) {
+                return Err(AppError::from(format!(
+                    "Context ID out of range: word '{}', left_id: {}, right_id: {}",
+                    self.word_data(word_id as i32),
+                    left_id,
+                    right_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn search(&self, text: &str, start: usize, callback: &mut dyn dictionary::Callback) {
+        self.trie
+            .each_common_prefix(text, start, |start: usize, length: usize, trie_id: i32| {
+                let trie_id = trie_id as usize;
+                for i in self.indices[trie_id]..self.indices[trie_id + 1] {
+                    let idx = i as usize;
+                    callback.call(ViterbiNode {
+                        word_id: i,
+                        start,
+                        length,
+                        cost: i32::from(self.costs[idx]),
+                        left_id: self.left_ids[idx],
+                        right_id: self.right_ids[idx],
+                        is_space: false,
+                        kind: NodeKind::User,
+                    });
+                }
+            });
+    }
+
+    /// 表層形と文脈IDが同じ単語の素性を、辞書に格納された順に返す
+    pub fn homographs(&self, surface: &str, left_id: i16, right_id: i16) -> Vec<&str> {
+        let trie_id = self.trie.search(surface);
+        if trie_id < 0 {
+            return Vec::new();
+        }
+        let trie_id = trie_id as usize;
+        (self.indices[trie_id]..self.indices[trie_id + 1])
+            .filter(|&i| {
+                self.left_ids[i as usize] == left_id && self.right_ids[i as usize] == right_id
+            })
+            .map(|i| self.word_data(i))
+            .collect()
+    }
+
+    pub fn word_data(&self, word_id: i32) -> &str {
+        let word_id = word_id as usize;
+        &self.data[(self.data_offsets[word_id] as usize)..(self.data_offsets[word_id + 1] as usize)]
+    }
+}
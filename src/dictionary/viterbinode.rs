@@ -1,13 +1,10 @@
-use std::rc::Rc;
+use crate::lattice::NodeKind;
 
-
-/// Viterbiアルゴリズムで使用されるノード
+/// 辞書の検索で見つかった、ラティスに追加される前の形態素ノード
 #[derive(Debug)]
 pub struct ViterbiNode {
-    /// 始点からノードまでの総コスト
+    /// 単語のコスト
     pub cost: i32,
-    /// コスト最小の前方のノードへのリンク
-    pub prev: Option<Rc<ViterbiNode>>,
 
     /// 単語ID
     pub word_id: i32,
@@ -15,13 +12,15 @@ pub struct ViterbiNode {
     pub left_id: i16,
     /// 右文脈ID
     pub right_id: i16,
-    /// 入力テキスト内での形態素の開始位置
+    /// 入力テキスト内での形態素の開始位置(バイト単位)
     pub start: usize,
-    /// 形態素の表層形の長さ(文字数)
-    pub length: i16,
+    /// 形態素の表層形の長さ(バイト単位)
+    pub length: usize,
 
     /// 形態素の文字種(文字カテゴリ)が空白文字かどうか
-    pub is_space: bool
+    pub is_space: bool,
+    /// 形態素の由来
+    pub kind: NodeKind
 }
 
 impl ViterbiNode {
@@ -34,7 +33,7 @@ impl ViterbiNode {
             left_id: 0,
             right_id: 0,
             is_space: false,
-            prev: None
+            kind: NodeKind::Bos
         }
     }
 }
\ No newline at end of file
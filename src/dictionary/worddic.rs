@@ -1,25 +1,51 @@
 use crate::dictionary;
+use crate::dictionary::build::KEY_PREFIX;
 use crate::dictionary::ViterbiNode;
-use crate::trie::Searcher;
+use crate::lattice::NodeKind;
+use crate::storage::{Array, Text, LEGACY_FORMAT_VERSION};
+use crate::trie::{node, Searcher};
 use crate::util::*;
-use crate::{Utf16Char, Utf16Str};
+use crate::Utf16Char;
 use log::debug;
-use std::io::{self, BufReader};
+use std::io;
+
+/// 単語辞書に登録された単語
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WordEntry<'dic, 'text> {
+    /// 単語の表層形
+    pub surface: &'text str,
+
+    /// 単語の素性
+    pub feature: &'dic str,
+
+    /// 単語ID
+    pub word_id: i32,
+
+    /// 左文脈ID
+    pub left_id: i16,
+
+    /// 右文脈ID
+    pub right_id: i16,
+
+    /// 単語のコスト
+    pub cost: i32,
+}
 
 #[derive(Clone)]
 pub struct WordDic {
     trie: Searcher,
-    data: String,
-    indices: Box<[i32]>,
+    data: Text,
+    indices: Array<i32>,
 
     /// costs[単語ID] = 単語のコスト
-    costs: Box<[i16]>,
+    costs: Array<i16>,
     /// left_ids[単語ID] = 単語の左文脈ID
-    left_ids: Box<[i16]>,
+    left_ids: Array<i16>,
     /// right_ids[単語ID] = 単語の右文脈ID
-    right_ids: Box<[i16]>,
+    right_ids: Array<i16>,
     /// data_offsets[単語ID] = 単語の素性データの開始位置
-    data_offsets: Box<[i32]>,
+    data_offsets: Array<i32>,
 }
 
 impl WordDic {
@@ -29,36 +55,42 @@ impl WordDic {
         let idx_path = "word.ary.idx";
         let inf_path = "word.inf";
 
-        let inf_size = dir.file_size(inf_path)?;
-        let mut reader = BufReader::new(dir.open(inf_path)?);
-        let word_count = (inf_size / (4 + 2 + 2 + 2)) as usize;
+        let mut inf = dir.open_dic_file(inf_path)?;
+        let word_count = inf.remaining() / (4 + 2 + 2 + 2);
         debug!("word_count: {}", word_count);
 
-        let word_data = read_all_as_chars(dir, dat_path)?;
-        let data_offsets = reader.get_int_array(word_count)?;
-        let (word_data, data_offsets) = convert2utf8_data(&word_data, &data_offsets);
+        let mut dat = dir.open_dic_file(dat_path)?;
+        let data_offsets = inf.read_array::<i32>(word_count)?;
+        let (data, data_offsets) = if dat.version() == LEGACY_FORMAT_VERSION {
+            // 旧形式の単語データはUTF-16で格納されている
+            let (data, data_offsets) =
+                convert2utf8_data(&dat.read_to_end::<Utf16Char>()?, &data_offsets);
+            (Text::from(data), data_offsets)
+        } else {
+            (Text::new(dat.read_to_end::<u8>()?)?, data_offsets)
+        };
 
         Ok(WordDic {
-            trie: Searcher::new(dir.open(word2id_path)?)?,
-            data: word_data,
-            indices: read_all_as_int_array(dir, idx_path)?,
+            trie: Searcher::from_dic_file(dir.open_dic_file(word2id_path)?)?,
+            data,
+            indices: dir.open_dic_file(idx_path)?.read_to_end()?,
 
             data_offsets,
-            left_ids: reader.get_short_array(word_count)?,
-            right_ids: reader.get_short_array(word_count)?,
-            costs: reader.get_short_array(word_count)?,
+            left_ids: inf.read_array(word_count)?,
+            right_ids: inf.read_array(word_count)?,
+            costs: inf.read_array(word_count)?,
         })
     }
 
-    pub fn search(&self, text: &Utf16Str, start: usize, callback: &mut dyn dictionary::Callback) {
+    pub fn search(&self, text: &str, start: usize, callback: &mut dyn dictionary::Callback) {
         self.trie
-            .each_common_prefix(text, start, |start: usize, offset: i32, trie_id: i32| {
+            .each_common_prefix(text, start, |start: usize, length: usize, trie_id: i32| {
                 /*
                  * common-prefix検索でキーが見つかった場合に呼び出されるクロージャー
                  * each_common_prefix()で該当するキーの部分文字列が見つかった都度に呼び出される
                  *
                  * @param start  入力テキストの検索開始位置
-                 * @param offset 一致した部分文字列の終端位置
+                 * @param length 一致した部分文字列の長さ(バイト単位)
                  * @param trie_id 一致した部分文字列のID
                  */
                 let trie_id = trie_id as usize;
@@ -69,17 +101,18 @@ impl WordDic {
                     callback.call(ViterbiNode {
                         word_id: i,
                         start,
-                        length: offset as i16,
+                        length,
                         cost: i32::from(self.costs[idx]),
                         left_id: self.left_ids[idx],
                         right_id: self.right_ids[idx],
                         is_space: false,
-                        prev: None,
+                        kind: NodeKind::Known,
                     });
                 }
             });
     }
 
+    /// 未知語処理用に、trieのIDを指定して単語を検索する
     pub fn search_from_trie_id(
         &self,
         trie_id: i32,
@@ -95,24 +128,93 @@ impl WordDic {
             callback.call(ViterbiNode {
                 word_id: i,
                 start,
-                length: word_length as i16,
+                length: word_length,
                 cost: i32::from(self.costs[idx]),
                 left_id: self.left_ids[idx],
                 right_id: self.right_ids[idx],
                 is_space,
-                prev: None,
+                kind: NodeKind::Unknown,
             });
         }
     }
 
+    /// 表層形が完全に一致する単語を、辞書に格納された順に返す
+    ///
+    /// 形態素解析を行わずに、辞書に登録されている単語を調べる. 未知語処理用の項目は含まない.
+    /// 予約された文字(U+0000, U+0001)を含む表層形の場合は空となる
+    /// # Arguments
+    /// * `surface` - 表層形
+    pub fn lookup<'a, 'b>(&'a self, surface: &'b str) -> Vec<WordEntry<'a, 'b>> {
+        if surface.is_empty() || surface.starts_with(KEY_PREFIX) || has_reserved(surface) {
+            return Vec::new();
+        }
+        let trie_id = self.trie.search(surface);
+        if trie_id < 0 {
+            return Vec::new();
+        }
+        self.entries(trie_id, surface).collect()
+    }
+
+    /// テキストの先頭から始まる部分文字列を表層形とする単語を、表層形の短い順に返す
+    ///
+    /// 表層形が同じ単語は辞書に格納された順に並ぶ. 未知語処理用の項目は含まない.
+    /// 予約された文字(U+0000, U+0001)を含むテキストの場合は空となる
+    /// # Arguments
+    /// * `text` - 検索対象のテキスト
+    pub fn common_prefix<'a, 'b>(&'a self, text: &'b str) -> Vec<WordEntry<'a, 'b>> {
+        let mut entries = Vec::new();
+        if text.starts_with(KEY_PREFIX) || has_reserved(text) {
+            return entries;
+        }
+        self.trie
+            .each_common_prefix(text, 0, |_, length: usize, trie_id: i32| {
+                entries.extend(self.entries(trie_id, &text[..length]));
+            });
+        entries
+    }
+
+    fn entries<'a, 'b>(
+        &'a self,
+        trie_id: i32,
+        surface: &'b str,
+    ) -> impl Iterator<Item = WordEntry<'a, 'b>> {
+        let trie_id = trie_id as usize;
+        (self.indices[trie_id]..self.indices[trie_id + 1]).map(move |i| {
+            let idx = i as usize;
+            WordEntry {
+                surface,
+                feature: self.word_data(i),
+                word_id: i,
+                left_id: self.left_ids[idx],
+                right_id: self.right_ids[idx],
+                cost: i32::from(self.costs[idx]),
+            }
+        })
+    }
+
+    /// 表層形と文脈IDが同じ単語の素性を、辞書に格納された順に返す
+    pub fn homographs(&self, surface: &str, left_id: i16, right_id: i16) -> Vec<&str> {
+        self.lookup(surface)
+            .into_iter()
+            .filter(|e| e.left_id == left_id && e.right_id == right_id)
+            .map(|e| e.feature)
+            .collect()
+    }
+
     pub fn word_data(&self, word_id: i32) -> &str {
         let word_id = word_id as usize;
-        &self.data[(self.data_offsets[word_id] as usize)..(self.data_offsets[word_id + 1] as usize)]
+        &self.data.as_str()
+            [(self.data_offsets[word_id] as usize)..(self.data_offsets[word_id + 1] as usize)]
     }
 }
 
+/// trieのキーに使用できない予約された文字を含むかどうか
+fn has_reserved(s: &str) -> bool {
+    s.bytes().any(node::chck::is_reserved)
+}
+
 // word_data()用に、予めString型へ変換しておく
-fn convert2utf8_data(utf16_str: &[Utf16Char], offsets: &[i32]) -> (String, Box<[i32]>) {
+fn convert2utf8_data(utf16_str: &[Utf16Char], offsets: &[i32]) -> (String, Array<i32>) {
     let mut buf = String::with_capacity(utf16_str.len() * 3);
     let mut new_offset = vec![0i32; offsets.len()];
 
@@ -125,5 +227,5 @@ fn convert2utf8_data(utf16_str: &[Utf16Char], offsets: &[i32]) -> (String, Box<[
     }
     debug!("buf size: {} / {}", buf.len(), buf.capacity());
 
-    (buf, new_offset.into_boxed_slice())
+    (buf, Array::from(new_offset))
 }
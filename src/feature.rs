@@ -0,0 +1,173 @@
+use std::borrow::Cow;
+
+/// 素性の各項目の位置
+///
+/// 素性はカンマ区切りの項目からなり、項目の並びは辞書の種類ごとに異なる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeatureSchema {
+    /// 品詞と品詞細分類の項目数. 素性の先頭から並んでいる
+    pub pos_levels: usize,
+    /// 活用型の項目の位置
+    pub conjugation_type: Option<usize>,
+    /// 活用形の項目の位置
+    pub conjugation_form: Option<usize>,
+    /// 原形(基本形)の項目の位置
+    pub base_form: Option<usize>,
+    /// 読みの項目の位置
+    pub reading: Option<usize>,
+    /// 発音の項目の位置
+    pub pronunciation: Option<usize>,
+}
+
+impl FeatureSchema {
+    /// IPADIC: 品詞,品詞細分類1,品詞細分類2,品詞細分類3,活用型,活用形,原形,読み,発音
+    pub const IPADIC: FeatureSchema = FeatureSchema {
+        pos_levels: 4,
+        conjugation_type: Some(4),
+        conjugation_form: Some(5),
+        base_form: Some(6),
+        reading: Some(7),
+        pronunciation: Some(8),
+    };
+
+    /// NAIST-jdic: 先頭の9項目はIPADICと同じ並び
+    pub const NAIST_JDIC: FeatureSchema = FeatureSchema::IPADIC;
+
+    /// UniDic(unidic-cwj 2.2以降): pos1,pos2,pos3,pos4,cType,cForm,lForm,lemma,orth,pron,orthBase,...,kana,...
+    ///
+    /// 原形は書字形基本形(orthBase)、読みは仮名形出現形(kana)、発音は発音形出現形(pron)とする
+    pub const UNIDIC: FeatureSchema = FeatureSchema {
+        pos_levels: 4,
+        conjugation_type: Some(4),
+        conjugation_form: Some(5),
+        base_form: Some(10),
+        reading: Some(20),
+        pronunciation: Some(9),
+    };
+}
+
+impl Default for FeatureSchema {
+    fn default() -> Self {
+        FeatureSchema::IPADIC
+    }
+}
+
+/// CSV形式の素性を項目ごとに分割する
+///
+/// ダブルクォートで囲まれた項目は囲みを外し、項目内の`""`を`"`に戻す
+pub fn split_feature(feature: &str) -> FeatureFields<'_> {
+    FeatureFields {
+        rest: Some(feature),
+    }
+}
+
+/// `split_feature`が返す、素性の項目のイテレータ
+pub struct FeatureFields<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Iterator for FeatureFields<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        let s = self.rest?;
+        if !s.starts_with('"') {
+            return Some(Cow::Borrowed(match s.find(',') {
+                Some(p) => {
+                    self.rest = Some(&s[(p + 1)..]);
+                    &s[..p]
+                }
+                None => {
+                    self.rest = None;
+                    s
+                }
+            }));
+        }
+
+        // 閉じクォートを探す. 閉じられていない場合は残り全体を一つの項目とする
+        let mut escaped = false;
+        let mut i = 1;
+        let (field, after) = loop {
+            match s[i..].find('"') {
+                Some(p) if s[(i + p + 1)..].starts_with('"') => {
+                    escaped = true;
+                    i += p + 2;
+                }
+                Some(p) => break (&s[1..(i + p)], &s[(i + p + 1)..]),
+                None => break (&s[1..], ""),
+            }
+        };
+        self.rest = after.find(',').map(|p| &after[(p + 1)..]);
+        Some(if escaped {
+            Cow::Owned(field.replace("\"\"", "\""))
+        } else {
+            Cow::Borrowed(field)
+        })
+    }
+}
+
+/// 素性の指定した位置の項目を取得する. 項目が無いか`*`の場合は`None`を返す
+pub(crate) fn field(feature: &str, idx: Option<usize>) -> Option<Cow<'_, str>> {
+    split_feature(feature)
+        .nth(idx?)
+        .filter(|field| field != "*")
+}
+
+/// 素性がパターンに一致するかどうか
+///
+/// パターンのカンマ区切りの各項目は素性の先頭から順に比較され、`*`は任意の値に一致する
+pub(crate) fn feature_matches(pattern: &str, feature: &str) -> bool {
+    let mut fields = split_feature(feature);
+    split_feature(pattern).all(|p| {
+        let field = fields.next();
+        p == "*" || field.as_deref() == Some(&*p)
+    })
+}
+
+/// 素性の項目を取得するメソッドを実装する
+macro_rules! impl_feature_accessors {
+    ($t:ty) => {
+        impl $t {
+            /// 品詞
+            pub fn pos(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, Some(0))
+            }
+
+            /// 品詞細分類
+            /// # Arguments
+            /// * `n` - 細分類の階層(1始まり)
+            pub fn pos_detail(&self, n: usize) -> Option<Cow<'_, str>> {
+                if n == 0 || n >= self.schema.pos_levels {
+                    return None;
+                }
+                crate::feature::field(&self.feature, Some(n))
+            }
+
+            /// 活用型
+            pub fn conjugation_type(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, self.schema.conjugation_type)
+            }
+
+            /// 活用形
+            pub fn conjugation_form(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, self.schema.conjugation_form)
+            }
+
+            /// 原形(基本形)
+            pub fn base_form(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, self.schema.base_form)
+            }
+
+            /// 読み
+            pub fn reading(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, self.schema.reading)
+            }
+
+            /// 発音
+            pub fn pronunciation(&self) -> Option<Cow<'_, str>> {
+                crate::feature::field(&self.feature, self.schema.pronunciation)
+            }
+        }
+    };
+}
@@ -0,0 +1,516 @@
+//! MeCab互換の解析結果の出力形式
+//!
+//! MeCabの`-O`オプションのプリセット(`default`, `wakati`, `yomi`, `chasen`, `dump`)と、
+//! `--node-format`等で指定する書式に対応する。
+//! 形態素のリストの他に、ラティス(`Tagger::lattice`)を渡して最適経路以外のノードも出力できる。
+//! 書式で使用できる指定子は以下の通り
+//!
+//! | 指定子 | 内容 |
+//! |--------|------|
+//! | `%m` | 形態素の表層形 |
+//! | `%M` | 直前の空白文字を含む表層形 |
+//! | `%H` | 素性 |
+//! | `%f[N]` | 素性のN番目(0始まり)の項目. `%f[N,M,...]`は複数の項目をカンマ区切りで出力する |
+//! | `%FC[N,M,...]` | 素性の複数の項目を文字Cで区切って出力する. 空か`*`の項目以降は省略する |
+//! | `%s` | 形態素の種類(0: 通常, 1: 未知語, 2: BOS, 3: EOS) |
+//! | `%pi` | 経路上での形態素の番号(BOSが0). 全てのノードを出力する場合はラティス上のノードの番号 |
+//! | `%S` | 入力テキスト |
+//! | `%L` | 入力テキストの長さ(バイト単位) |
+//! | `%c`, `%pw` | 単語のコスト |
+//! | `%pC` | 直前の形態素との連接コスト |
+//! | `%pn` | 連接コストと単語のコストの和 |
+//! | `%pc` | 文頭からの累積コスト |
+//! | `%P`, `%pP` | 周辺確率(`Tagger::parse_with_marginals`で解析した場合、またはラティスを出力する場合のみ) |
+//! | `%pS` | 形態素の直前の空白文字 |
+//! | `%ps`, `%pe` | 形態素の開始・終了位置(バイト単位) |
+//! | `%pl`, `%pL` | 表層形の長さ、直前の空白文字を含む長さ(バイト単位) |
+//! | `%phl`, `%phr` | 左文脈ID、右文脈ID |
+//! | `%pb` | 最適経路上のノードであれば`*`, それ以外は空白 |
+//! | `%%` | `%` |
+//!
+//! エスケープシーケンス`\t`, `\n`, `\r`, `\s`(空白), `\\`も使用できる
+
+use std::fmt::Write as _;
+use std::io;
+use std::ops::Range;
+
+use crate::dictionary::build::{AppError, AppResult};
+use crate::dictionary::ViterbiNode;
+use crate::feature::split_feature;
+use crate::lattice::{Lattice, NodeKind};
+use crate::morpheme::Morpheme;
+use crate::tagger::Tagger;
+
+/// 書式を構成する要素
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Literal(String),
+    Surface,
+    SurfaceWithSpace,
+    Feature,
+    /// 素性の項目. 項目の番号のリスト、区切り文字、空か`*`の項目以降を省略するかどうか
+    Fields(Vec<usize>, char, bool),
+    Status,
+    Sentence,
+    SentenceLen,
+    WordCost,
+    ConnectionCost,
+    NodeCost,
+    Cost,
+    Prob,
+    Index,
+    Space,
+    Start,
+    End,
+    SurfaceLen,
+    SurfaceWithSpaceLen,
+    LeftId,
+    RightId,
+    Best,
+}
+
+/// 書式
+#[derive(Debug, Clone, PartialEq)]
+struct Template(Vec<Item>);
+
+impl Template {
+    /// 周辺確率を出力する指定子を含むかどうか
+    fn uses_prob(&self) -> bool {
+        self.0.contains(&Item::Prob)
+    }
+
+    fn parse(format: &str) -> AppResult<Template> {
+        let mut items = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars();
+        let error = |msg: &str| AppError::from(format!("{}: {}", msg, format));
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => literal.push(match chars.next() {
+                    Some('t') => '\t',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('s') => ' ',
+                    Some('\\') => '\\',
+                    _ => return Err(error("Invalid escape sequence in format")),
+                }),
+                '%' => {
+                    let item = match chars.next() {
+                        Some('%') => {
+                            literal.push('%');
+                            continue;
+                        }
+                        Some('m') => Item::Surface,
+                        Some('M') => Item::SurfaceWithSpace,
+                        Some('H') => Item::Feature,
+                        Some('s') => Item::Status,
+                        Some('S') => Item::Sentence,
+                        Some('L') => Item::SentenceLen,
+                        Some('c') => Item::WordCost,
+                        Some('P') => Item::Prob,
+                        Some('f') => Item::Fields(
+                            parse_indices(&mut chars)
+                                .ok_or_else(|| error("Invalid %f[...] in format"))?,
+                            ',',
+                            false,
+                        ),
+                        Some('F') => {
+                            let sep = chars.next().ok_or_else(|| error("Invalid %F in format"))?;
+                            Item::Fields(
+                                parse_indices(&mut chars)
+                                    .ok_or_else(|| error("Invalid %F[...] in format"))?,
+                                sep,
+                                true,
+                            )
+                        }
+                        Some('p') => match chars.next() {
+                            Some('w') => Item::WordCost,
+                            Some('C') => Item::ConnectionCost,
+                            Some('n') => Item::NodeCost,
+                            Some('c') => Item::Cost,
+                            Some('P') => Item::Prob,
+                            Some('i') => Item::Index,
+                            Some('S') => Item::Space,
+                            Some('s') => Item::Start,
+                            Some('e') => Item::End,
+                            Some('l') => Item::SurfaceLen,
+                            Some('L') => Item::SurfaceWithSpaceLen,
+                            Some('b') => Item::Best,
+                            Some('h') => match chars.next() {
+                                Some('l') => Item::LeftId,
+                                Some('r') => Item::RightId,
+                                _ => return Err(error("Unknown %ph specifier in format")),
+                            },
+                            _ => return Err(error("Unknown %p specifier in format")),
+                        },
+                        _ => return Err(error("Unknown specifier in format")),
+                    };
+                    if !literal.is_empty() {
+                        items.push(Item::Literal(literal.split_off(0)));
+                    }
+                    items.push(item);
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            items.push(Item::Literal(literal));
+        }
+        Ok(Template(items))
+    }
+}
+
+/// `[N,M,...]`の形式の項目の番号のリストを読み込む
+fn parse_indices<I: Iterator<Item = char>>(chars: &mut I) -> Option<Vec<usize>> {
+    if chars.next() != Some('[') {
+        return None;
+    }
+    let mut list = String::new();
+    for c in chars {
+        if c == ']' {
+            return list.split(',').map(|n| n.trim().parse().ok()).collect();
+        }
+        list.push(c);
+    }
+    None
+}
+
+/// 書式に従って出力するノードの情報
+struct Node<'a> {
+    index: usize,
+    status: u8,
+    surface: &'a str,
+    feature: &'a str,
+    /// テキスト内での範囲(バイト単位)
+    range: Range<usize>,
+    /// 直前の空白文字
+    space: &'a str,
+    left_id: i16,
+    right_id: i16,
+    word_cost: i32,
+    /// 直前のノードとの連接コスト
+    conn_cost: i32,
+    /// 文頭からの累積コスト
+    cost: i32,
+    prob: Option<f64>,
+    /// 最適経路上のノードかどうか
+    best: bool,
+}
+
+impl<'a> Node<'a> {
+    fn boseos(index: usize, status: u8, pos: usize, space: &'a str) -> Node<'a> {
+        Node {
+            index,
+            status,
+            surface: "",
+            feature: "BOS/EOS",
+            range: pos..pos,
+            space,
+            left_id: 0,
+            right_id: 0,
+            word_cost: 0,
+            conn_cost: 0,
+            cost: 0,
+            prob: None,
+            best: true,
+        }
+    }
+}
+
+/// 解析結果をMeCab互換の形式で出力する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formatter {
+    node: Template,
+    unk: Option<Template>,
+    bos: Template,
+    eos: Template,
+    /// ラティスを出力する際に、最適経路以外のノードも出力するかどうか
+    all_morphs: bool,
+    /// ラティスを出力する際に、周辺確率を求めるためにコストに掛ける係数
+    theta: f64,
+}
+
+impl Formatter {
+    /// 書式を指定して作成する
+    /// # Arguments
+    /// * `node_format` - 形態素の書式(MeCabの`--node-format`)
+    /// * `eos_format`  - 文末の書式(MeCabの`--eos-format`)
+    pub fn new(node_format: &str, eos_format: &str) -> AppResult<Formatter> {
+        Ok(Formatter {
+            node: Template::parse(node_format)?,
+            unk: None,
+            bos: Template(Vec::new()),
+            eos: Template::parse(eos_format)?,
+            all_morphs: false,
+            theta: 0.75,
+        })
+    }
+
+    /// MeCabの`-O`オプションのプリセットの書式で作成する
+    ///
+    /// `dump`は、ラティスを出力する場合に全てのノードを出力する
+    /// # Arguments
+    /// * `name` - プリセットの名前(`default`, `wakati`, `yomi`, `chasen`, `dump`)
+    pub fn preset(name: &str) -> AppResult<Formatter> {
+        Ok(match name {
+            "default" => Formatter::new("%m\\t%H\\n", "EOS\\n")?,
+            "wakati" => Formatter::new("%m\\s", "\\n")?,
+            "yomi" => {
+                let mut f = Formatter::new("%pS%f[7]", "\\n")?;
+                f.set_unk_format("%M")?;
+                f
+            }
+            "chasen" => {
+                let mut f = Formatter::new(
+                    "%m\\t%f[7]\\t%f[6]\\t%F-[0,1,2,3]\\t%f[4]\\t%f[5]\\n",
+                    "EOS\\n",
+                )?;
+                f.set_unk_format("%m\\t%m\\t%m\\t%F-[0,1,2,3]\\t\\t\\n")?;
+                f
+            }
+            "dump" => {
+                let dump = "%pi %m %H %ps %pe %phr %phl %s %pb %pP %pw %pC %pc\\n";
+                let mut f = Formatter::new(dump, dump)?;
+                f.set_bos_format(dump)?;
+                f.set_all_morphs(true);
+                f
+            }
+            _ => return Err(AppError::from(format!("Unknown output format: {}", name))),
+        })
+    }
+
+    /// 未知語の書式を設定する(MeCabの`--unk-format`). 設定しない場合は形態素の書式を使う
+    pub fn set_unk_format(&mut self, format: &str) -> AppResult<()> {
+        self.unk = Some(Template::parse(format)?);
+        Ok(())
+    }
+
+    /// 文頭の書式を設定する(MeCabの`--bos-format`). 既定では何も出力しない
+    pub fn set_bos_format(&mut self, format: &str) -> AppResult<()> {
+        self.bos = Template::parse(format)?;
+        Ok(())
+    }
+
+    /// 文末の書式を設定する(MeCabの`--eos-format`)
+    pub fn set_eos_format(&mut self, format: &str) -> AppResult<()> {
+        self.eos = Template::parse(format)?;
+        Ok(())
+    }
+
+    /// ラティスを出力する際に、最適経路以外のノードも出力するかどうかを設定する(MeCabの`-a`)
+    pub fn set_all_morphs(&mut self, all_morphs: bool) {
+        self.all_morphs = all_morphs;
+    }
+
+    /// ラティスを出力する際に、周辺確率を求めるためにコストに掛ける係数を設定する(MeCabの`--theta`). 既定値は0.75
+    pub fn set_theta(&mut self, theta: f64) {
+        self.theta = theta;
+    }
+
+    /// 解析結果を書式に従って文字列にする
+    ///
+    /// 形態素のリストは一つの経路として扱い、連接コストと累積コストはその経路に沿って求める.
+    /// `%pb`は全ての形態素で`*`となる
+    /// # Arguments
+    /// * `tagger`    - `morphemes`を解析した形態素解析器
+    /// * `text`      - 解析対象テキスト
+    /// * `morphemes` - `text`の解析結果
+    pub fn format(&self, tagger: &Tagger, text: &str, morphemes: &[Morpheme]) -> String {
+        let mut out = String::new();
+        let mut prev_end = 0;
+        let mut prev_right_id = ViterbiNode::make_boseos().right_id;
+        let mut cost = 0;
+        self.render(&self.bos, &mut out, text, &Node::boseos(0, 2, 0, ""));
+        for (i, m) in morphemes.iter().enumerate() {
+            let template = match (&self.unk, m.kind) {
+                (Some(unk), NodeKind::Unknown) => unk,
+                _ => &self.node,
+            };
+            let conn_cost = tagger.link_cost(prev_right_id, m.left_id);
+            cost += conn_cost + m.word_cost;
+            self.render(
+                template,
+                &mut out,
+                text,
+                &Node {
+                    index: i + 1,
+                    status: if m.kind == NodeKind::Unknown { 1 } else { 0 },
+                    surface: m.surface,
+                    feature: m.feature,
+                    range: m.byte_range.clone(),
+                    space: text.get(prev_end..m.byte_range.start).unwrap_or(""),
+                    left_id: m.left_id,
+                    right_id: m.right_id,
+                    word_cost: m.word_cost,
+                    conn_cost,
+                    cost,
+                    prob: m.prob,
+                    best: true,
+                },
+            );
+            prev_end = m.byte_range.end;
+            prev_right_id = m.right_id;
+        }
+        let mut eos = Node::boseos(
+            morphemes.len() + 1,
+            3,
+            text.len(),
+            text.get(prev_end..).unwrap_or(""),
+        );
+        eos.conn_cost = tagger.link_cost(prev_right_id, ViterbiNode::make_boseos().left_id);
+        eos.cost = cost + eos.conn_cost;
+        self.render(&self.eos, &mut out, text, &eos);
+        out
+    }
+
+    /// ラティスを書式に従って文字列にする
+    ///
+    /// 既定では最適経路上のノードを出力し、`set_all_morphs(true)`の場合(`dump`)はラティス上の全てのノードを出力する.
+    /// 書式が`%P`, `%pP`を含む場合は、`Tagger::marginals`で各ノードの周辺確率を求める
+    /// # Arguments
+    /// * `tagger`  - ラティスを作成した形態素解析器
+    /// * `text`    - 解析対象テキスト
+    /// * `lattice` - `Tagger::lattice`で作成した`text`のラティス
+    pub fn format_lattice(&self, tagger: &Tagger, text: &str, lattice: &Lattice) -> String {
+        let mut path = lattice.best_path();
+        let mut best = vec![false; lattice.nodes().len()];
+        for &idx in &path {
+            best[idx] = true;
+        }
+        best[lattice.bos()] = true;
+        best[lattice.eos()] = true;
+
+        let indices: Vec<usize> = if self.all_morphs {
+            (0..lattice.nodes().len()).collect()
+        } else {
+            path.insert(0, lattice.bos());
+            path.push(lattice.eos());
+            path
+        };
+
+        let templates = [&self.node, &self.bos, &self.eos];
+        let uses_prob = templates
+            .iter()
+            .chain(self.unk.as_ref().iter())
+            .any(|t| t.uses_prob());
+        let marginals = if uses_prob {
+            Some(tagger.marginals(lattice, self.theta))
+        } else {
+            None
+        };
+
+        let mut out = String::new();
+        for (i, &idx) in indices.iter().enumerate() {
+            let n = lattice.node(idx);
+            let (template, status) = match n.kind {
+                NodeKind::Bos => (&self.bos, 2),
+                NodeKind::Eos => (&self.eos, 3),
+                NodeKind::Unknown => (self.unk.as_ref().unwrap_or(&self.node), 1),
+                NodeKind::Known | NodeKind::User => (&self.node, 0),
+            };
+            let (prev_end, prev_cost) = match n.prev {
+                Some(p) => (lattice.node(p).end(), lattice.node(p).cost),
+                None => (n.start, 0),
+            };
+            self.render(
+                template,
+                &mut out,
+                text,
+                &Node {
+                    index: i,
+                    status,
+                    surface: &text[n.start..n.end()],
+                    feature: tagger.feature(n),
+                    range: n.start..n.end(),
+                    space: text.get(prev_end..n.start).unwrap_or(""),
+                    left_id: n.left_id,
+                    right_id: n.right_id,
+                    word_cost: n.word_cost,
+                    conn_cost: n.cost - prev_cost - n.word_cost,
+                    cost: n.cost,
+                    prob: marginals.as_ref().map(|m| m[idx]),
+                    best: best[idx],
+                },
+            );
+        }
+        out
+    }
+
+    /// 解析結果を書式に従って書き出す
+    /// # Arguments
+    /// * `writer`    - 書き出し先
+    /// * `tagger`    - `morphemes`を解析した形態素解析器
+    /// * `text`      - 解析対象テキスト
+    /// * `morphemes` - `text`の解析結果
+    pub fn write<W: io::Write>(
+        &self,
+        mut writer: W,
+        tagger: &Tagger,
+        text: &str,
+        morphemes: &[Morpheme],
+    ) -> io::Result<()> {
+        writer.write_all(self.format(tagger, text, morphemes).as_bytes())
+    }
+
+    /// ラティスを書式に従って書き出す
+    /// # Arguments
+    /// * `writer`  - 書き出し先
+    /// * `tagger`  - ラティスを作成した形態素解析器
+    /// * `text`    - 解析対象テキスト
+    /// * `lattice` - `text`のラティス
+    pub fn write_lattice<W: io::Write>(
+        &self,
+        mut writer: W,
+        tagger: &Tagger,
+        text: &str,
+        lattice: &Lattice,
+    ) -> io::Result<()> {
+        writer.write_all(self.format_lattice(tagger, text, lattice).as_bytes())
+    }
+
+    fn render(&self, template: &Template, out: &mut String, text: &str, node: &Node) {
+        let (surface, feature) = (node.surface, node.feature);
+        let (word_cost, conn_cost, cost) = (node.word_cost, node.conn_cost, node.cost);
+        for item in &template.0 {
+            // NOTE: Stringへの書き込みは失敗しない
+            let _ = match item {
+                Item::Literal(s) => write!(out, "{}", s),
+                Item::Surface => write!(out, "{}", surface),
+                Item::SurfaceWithSpace => write!(out, "{}{}", node.space, surface),
+                Item::Feature => write!(out, "{}", feature),
+                Item::Fields(indices, sep, omit) => {
+                    let fields: Vec<_> = split_feature(feature).collect();
+                    for (i, &n) in indices.iter().enumerate() {
+                        let field = fields.get(n).map_or("*", |f| f);
+                        if *omit && (field.is_empty() || field == "*") {
+                            break;
+                        }
+                        if i > 0 {
+                            out.push(*sep);
+                        }
+                        out.push_str(field);
+                    }
+                    Ok(())
+                }
+                Item::Status => write!(out, "{}", node.status),
+                Item::Sentence => write!(out, "{}", text),
+                Item::SentenceLen => write!(out, "{}", text.len()),
+                Item::WordCost => write!(out, "{}", word_cost),
+                Item::ConnectionCost => write!(out, "{}", conn_cost),
+                Item::NodeCost => write!(out, "{}", conn_cost + word_cost),
+                Item::Cost => write!(out, "{}", cost),
+                Item::Prob => write!(out, "{:.6}", node.prob.unwrap_or(0.0)),
+                Item::Index => write!(out, "{}", node.index),
+                Item::Space => write!(out, "{}", node.space),
+                Item::Start => write!(out, "{}", node.range.start),
+                Item::End => write!(out, "{}", node.range.end),
+                Item::SurfaceLen => write!(out, "{}", surface.len()),
+                Item::SurfaceWithSpaceLen => write!(out, "{}", node.space.len() + surface.len()),
+                Item::LeftId => write!(out, "{}", node.left_id),
+                Item::RightId => write!(out, "{}", node.right_id),
+                Item::Best => write!(out, "{}", if node.best { '*' } else { ' ' }),
+            };
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::dictionary::ViterbiNode;
+
+/// ラティス上のノードの由来
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeKind {
+    /// 文頭
+    Bos,
+    /// 文末
+    Eos,
+    /// 単語辞書(`WordDic`)に登録されている形態素
+    Known,
+    /// 未知語処理(`Unknown`)により生成された形態素
+    Unknown,
+    /// ユーザー辞書(`UserDic`)に登録されている形態素
+    User,
+}
+
+/// ラティス上のノード
+#[derive(Debug, Clone)]
+pub struct LatticeNode {
+    /// 単語ID
+    pub word_id: i32,
+    /// 左文脈ID
+    pub left_id: i16,
+    /// 右文脈ID
+    pub right_id: i16,
+    /// 単語のコスト
+    pub word_cost: i32,
+    /// 始点からノードまでの最小の総コスト
+    pub cost: i32,
+    /// コスト最小の前方のノードのインデックス
+    pub prev: Option<usize>,
+    /// 入力テキスト内での形態素の開始位置(バイト単位)
+    pub start: usize,
+    /// 形態素の表層形の長さ(バイト単位)
+    pub length: usize,
+    /// ノードの由来
+    pub kind: NodeKind,
+    /// コスト最小の経路上で後方にあるノードのインデックス. `Lattice::link_best_path`で設定される
+    next: Option<usize>,
+    /// ユーザー辞書のノードの場合、そのユーザー辞書のID. それ以外は0
+    user_dic_id: u32,
+}
+
+impl LatticeNode {
+    fn from_viterbi_node(vn: &ViterbiNode) -> LatticeNode {
+        LatticeNode {
+            word_id: vn.word_id,
+            left_id: vn.left_id,
+            right_id: vn.right_id,
+            word_cost: vn.cost,
+            cost: vn.cost,
+            prev: None,
+            start: vn.start,
+            length: vn.length,
+            kind: vn.kind,
+            next: None,
+            user_dic_id: 0,
+        }
+    }
+
+    fn make_boseos(kind: NodeKind, start: usize) -> LatticeNode {
+        let mut node = Self::from_viterbi_node(&ViterbiNode::make_boseos());
+        node.start = start;
+        node.kind = kind;
+        node
+    }
+
+    /// ノードの終了位置(バイト単位)
+    pub fn end(&self) -> usize {
+        self.start + self.length
+    }
+
+    /// ユーザー辞書のノードの場合、そのユーザー辞書のID
+    pub(crate) fn user_dic_id(&self) -> u32 {
+        self.user_dic_id
+    }
+}
+
+/// 形態素ラティス
+///
+/// 単語辞書と未知語処理で得られた全ての候補ノードを保持する。
+/// 各ノードは`nodes()`のインデックスで参照される。
+#[derive(Debug, Clone)]
+pub struct Lattice {
+    len: usize,
+    nodes: Vec<LatticeNode>,
+    begin_nodes: Vec<Vec<usize>>,
+    end_nodes: Vec<Vec<usize>>,
+    eos: Option<usize>,
+    /// ラティスの構築に使用したユーザー辞書のID. ユーザー辞書が無い場合は0
+    user_dic_id: u32,
+}
+
+impl Lattice {
+    /// BOSノードのみを持つラティスを作成する
+    /// # Arguments
+    /// * `len` - 入力テキストの長さ(バイト単位)
+    pub(crate) fn new(len: usize) -> Lattice {
+        let mut lattice = Lattice {
+            len: 0,
+            nodes: Vec::new(),
+            begin_nodes: Vec::new(),
+            end_nodes: Vec::new(),
+            eos: None,
+            user_dic_id: 0,
+        };
+        lattice.reset(len, 0);
+        lattice
+    }
+
+    /// 確保済みの領域を再利用して、BOSノードのみを持つラティスに戻す
+    /// # Arguments
+    /// * `len`         - 入力テキストの長さ(バイト単位)
+    /// * `user_dic_id` - 以降に追加するユーザー辞書のノードの由来となるユーザー辞書のID
+    pub(crate) fn reset(&mut self, len: usize, user_dic_id: u32) {
+        let used = (self.len + 1).min(self.begin_nodes.len());
+        for pos in 0..used {
+            self.begin_nodes[pos].clear();
+            self.end_nodes[pos].clear();
+        }
+        // NOTE: 以前より短いテキストでも各位置のリストは解放せずに残しておく
+        if self.begin_nodes.len() < len + 1 {
+            self.begin_nodes.resize_with(len + 1, Vec::new);
+            self.end_nodes.resize_with(len + 1, Vec::new);
+        }
+        self.len = len;
+        self.nodes.clear();
+        self.eos = None;
+        self.user_dic_id = user_dic_id;
+
+        self.nodes.push(LatticeNode::make_boseos(NodeKind::Bos, 0));
+        self.end_nodes[0].push(0);
+    }
+
+    /// ノードを追加し、そのインデックスを返す
+    /// # Arguments
+    /// * `vn`        - 追加するノード
+    /// * `prev`      - コスト最小の前方のノードのインデックス
+    /// * `prev_cost` - 始点から`prev`を経由してノードに連接するまでのコスト
+    pub(crate) fn add(&mut self, vn: &ViterbiNode, prev: usize, prev_cost: i32) -> usize {
+        let mut node = LatticeNode::from_viterbi_node(vn);
+        node.cost = prev_cost + node.word_cost;
+        node.prev = Some(prev);
+        if node.kind == NodeKind::User {
+            node.user_dic_id = self.user_dic_id;
+        }
+        self.push(node)
+    }
+
+    /// 空白文字の区間`start..end`を読み飛ばして、`start`で終わるノードを`end`に連接できるようにする
+    pub(crate) fn skip_space(&mut self, start: usize, end: usize) {
+        let (head, tail) = self.end_nodes.split_at_mut(end);
+        tail[0].extend_from_slice(&head[start]);
+    }
+
+    /// EOSノードを追加する
+    pub(crate) fn add_eos(&mut self, prev: usize, prev_cost: i32) {
+        let mut node = LatticeNode::make_boseos(NodeKind::Eos, self.len());
+        node.cost = prev_cost;
+        node.prev = Some(prev);
+        self.eos = Some(self.push(node));
+    }
+
+    fn push(&mut self, node: LatticeNode) -> usize {
+        let idx = self.nodes.len();
+        self.begin_nodes[node.start].push(idx);
+        if node.kind != NodeKind::Eos {
+            self.end_nodes[node.end()].push(idx);
+        }
+        self.nodes.push(node);
+        idx
+    }
+
+    /// 入力テキストの長さ(バイト単位)
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 入力テキストが空かどうか
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 全てのノード
+    pub fn nodes(&self) -> &[LatticeNode] {
+        &self.nodes
+    }
+
+    /// 指定したインデックスのノード
+    pub fn node(&self, idx: usize) -> &LatticeNode {
+        &self.nodes[idx]
+    }
+
+    /// BOSノードのインデックス
+    pub fn bos(&self) -> usize {
+        0
+    }
+
+    /// EOSノードのインデックス
+    pub fn eos(&self) -> usize {
+        self.eos.expect("lattice is not finished")
+    }
+
+    /// 位置`pos`から始まるノードのインデックスのリスト
+    pub fn begin_nodes(&self, pos: usize) -> &[usize] {
+        &self.begin_nodes[pos]
+    }
+
+    /// 位置`pos`から始まるノードに連接可能なノードのインデックスのリスト
+    ///
+    /// `pos`で終わるノードの他に、空白文字を読み飛ばして`pos`に連接するノードを含む
+    pub fn end_nodes(&self, pos: usize) -> &[usize] {
+        &self.end_nodes[pos]
+    }
+
+    /// コスト最小の経路上のノードのインデックスのリスト(BOS/EOSを除く)
+    pub fn best_path(&self) -> Vec<usize> {
+        let mut path = Vec::new();
+        self.best_path_into(&mut path);
+        path
+    }
+
+    /// コスト最小の経路上のノードのインデックスを、`path`の内容と置き換える
+    pub fn best_path_into(&self, path: &mut Vec<usize>) {
+        path.clear();
+        let mut cur = self.nodes[self.eos()].prev;
+        while let Some(idx) = cur {
+            if idx == self.bos() {
+                break;
+            }
+            path.push(idx);
+            cur = self.nodes[idx].prev;
+        }
+        path.reverse();
+    }
+
+    /// コスト最小の経路をEOSから辿り、経路上の各ノードに後方のノードへのリンクを記録する
+    ///
+    /// 経路を保持するリストを作らずに、`next_on_best_path`で経路を先頭から順に辿れるようにする
+    /// # Return
+    /// 経路の先頭のノードのインデックスと、経路上のノードの数(BOS/EOSを除く)の組
+    pub(crate) fn link_best_path(&mut self) -> (Option<usize>, usize) {
+        let mut next = None;
+        let mut len = 0;
+        let mut cur = self.nodes[self.eos()].prev;
+        while let Some(idx) = cur {
+            if idx == self.bos() {
+                break;
+            }
+            self.nodes[idx].next = next;
+            next = Some(idx);
+            len += 1;
+            cur = self.nodes[idx].prev;
+        }
+        (next, len)
+    }
+
+    /// コスト最小の経路上で、指定したノードの後方にあるノードのインデックス
+    ///
+    /// 直前の`link_best_path`で辿った経路上のノードに対してのみ有効
+    pub(crate) fn next_on_best_path(&self, idx: usize) -> Option<usize> {
+        self.nodes[idx].next
+    }
+}
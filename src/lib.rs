@@ -1,25 +1,90 @@
 mod tagger;
 mod trie;
 mod util;
-pub use tagger::Tagger;
+pub use tagger::{ParseContext, Surfaces, Tagger, Tokens};
+mod lattice;
+pub use lattice::{Lattice, LatticeNode, NodeKind};
+mod constraint;
+pub use constraint::Constraints;
+mod storage;
+mod stream;
+pub use stream::{TokenStream, DEFAULT_MAX_CHUNK_LEN};
+#[cfg(any(feature = "zip", feature = "tar"))]
+mod archive;
+mod bundle;
+#[cfg(feature = "tar")]
+pub use archive::TarArchive;
+pub use bundle::Bundle;
+#[macro_use]
+mod feature;
+pub use feature::{split_feature, FeatureFields, FeatureSchema};
+mod format;
+pub use format::Formatter;
+mod conllu;
+pub use conllu::{ConlluMapping, ConlluWriter};
 mod morpheme;
 pub use morpheme::{Morpheme, MorphemeBuf};
 pub mod dictionary;
-pub use util::DirLike;
+pub use storage::DicFile;
+#[cfg(feature = "mmap")]
+pub use util::MmapDir;
+pub use util::{DirLike, SubDir};
 
 type Utf16Char = u16;
-type Utf16String = Vec<Utf16Char>;
-type Utf16Str = [Utf16Char];
 
 #[cfg(test)]
 mod tests {
-    use crate::dictionary::build;
-    use crate::morpheme::MorphemeBuf;
+    use crate::dictionary::{build, UserDic};
+    use crate::morpheme::{Morpheme, MorphemeBuf};
     use crate::tagger::Tagger;
+    use crate::{
+        split_feature, Bundle, ConlluMapping, ConlluWriter, Constraints, DicFile, DirLike,
+        FeatureSchema, Formatter, NodeKind, ParseContext,
+    };
+    use std::collections::HashMap;
     use std::path::{Path, PathBuf};
 
     const TEST_DIC_SRC_PATH: &str = "tiny_test_dic/src";
     const TEST_DIC_DST_PATH: &str = "tiny_test_dic/out";
+    const TEST_USER_DIC_PATH: &str = "tiny_test_dic/user.csv";
+
+    /// 現在のスレッドでのヒープ領域の確保の回数を数えるアロケータ
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCS.with(|c| c.set(c.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            ALLOCS.with(|c| c.set(c.get() + 1));
+            std::alloc::System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    /// `f`の実行中に確保した領域の数を返す
+    fn count_allocs<F: FnOnce()>(f: F) -> usize {
+        let before = ALLOCS.with(|c| c.get());
+        f();
+        ALLOCS.with(|c| c.get()) - before
+    }
 
     fn setup_tagger() -> Tagger {
         let dic_dir = PathBuf::from(TEST_DIC_DST_PATH);
@@ -32,14 +97,35 @@ mod tests {
 
         test_tagger();
         test_wakati();
+        test_parse_nbest();
+        test_lattice();
+        test_marginals();
+        test_parse_with_constraints();
+        test_user_dic();
+        test_corrupted_dic();
+        test_bundle();
+        test_in_memory_dir();
+        test_dictionary_builder();
+        test_deprecated_builders();
+        test_validate_dic();
+        test_homographs();
+        test_word_dic_lookup();
+        test_parse_batch();
+        test_parse_into();
+        test_feature_accessors();
+        test_token_stream();
+        test_formatter();
+        test_conllu();
+        #[cfg(feature = "mmap")]
+        test_mmap();
     }
 
     fn build_dic() {
         build_matrix_def_file();
 
         build::build_dic(
-            &Path::new(TEST_DIC_SRC_PATH),
-            &Path::new(TEST_DIC_DST_PATH),
+            Path::new(TEST_DIC_SRC_PATH),
+            Path::new(TEST_DIC_DST_PATH),
             ",".to_string(),
             "UTF-8",
         )
@@ -50,7 +136,7 @@ mod tests {
         let src_dir = Path::new(TEST_DIC_SRC_PATH);
         let sparse_matrix = src_dir.join("matrix.def.sparse");
         let dense_matrix = src_dir.join("matrix.def");
-        let default_cost = ::std::i16::MAX;
+        let default_cost = i16::MAX;
         build::matrix::convert_sparse2dense(&sparse_matrix, &dense_matrix, default_cost).unwrap();
 
         if !dense_matrix.exists() {
@@ -98,6 +184,37 @@ mod tests {
         assert_eq!("もも", buf.surface);
         assert_eq!("名詞,一般,*,*,*,*,もも,モモ,モモ", buf.feature);
         assert_eq!(7, buf.start);
+        assert_eq!(results[2].word_id, buf.word_id);
+        assert_eq!(results[2].left_id, buf.left_id);
+        assert_eq!(results[2].word_cost, buf.word_cost);
+        assert_eq!(NodeKind::Known, buf.kind);
+        assert!(!buf.is_unknown());
+
+        let results = tagger.parse("ABCも");
+        assert!(results[0].is_unknown());
+        assert!(!results[1].is_unknown());
+
+        // 各単位の位置
+        let text = "😀すもも";
+        let results = tagger.parse(text);
+        let last = results.last().unwrap();
+        assert_eq!("すもも", &text[last.byte_range.clone()]);
+        assert_eq!(4..13, last.byte_range);
+        assert_eq!(1..4, last.char_range);
+        assert_eq!(2, last.start);
+        assert_eq!(last.byte_range, last.to_owned().byte_range);
+
+        // 基本多言語面より上の文字はコードポイント単位で文字カテゴリを判定する
+        let results = tagger.parse("𠮷𠮷𠮷も");
+        assert_eq!("𠮷𠮷𠮷も", surfaces(&results).concat());
+        for m in &results[..(results.len() - 1)] {
+            assert!(m.is_unknown());
+            assert_eq!(Some("名詞"), m.pos().as_deref());
+            assert!((1..=2).contains(&m.char_range.len())); // KANJIの未知語は2文字まで
+        }
+        let results = tagger.parse("😀😀も");
+        assert_eq!(vec!["😀😀", "も"], surfaces(&results));
+        assert_eq!(Some("記号"), results[0].pos().as_deref());
     }
 
     fn test_wakati() {
@@ -107,10 +224,834 @@ mod tests {
         let results = tagger.wakati(text);
         assert_eq!(7, results.len());
 
-        let v = vec!["すもも", "も", "もも", "も", "もも", "の", "うち"]
+        let v = ["すもも", "も", "もも", "も", "もも", "の", "うち"]
             .iter()
             .map(|s| s.to_string())
             .collect::<Vec<_>>();
         assert_eq!(v, results);
+
+        let iter = tagger.wakati_iter(text);
+        assert_eq!(7, iter.len());
+        assert_eq!(v, iter.collect::<Vec<_>>());
+        assert_eq!(Some("うち"), tagger.wakati_iter(text).next_back());
+
+        let tokens = tagger.tokens(text);
+        assert_eq!(7, tokens.len());
+        assert_eq!(
+            features(&tagger.parse(text)),
+            features(&tokens.collect::<Vec<_>>())
+        );
+        let nouns = tagger
+            .tokens(text)
+            .filter(|m| m.pos().as_deref() == Some("名詞"));
+        assert_eq!(
+            vec![0, 4, 7, 10],
+            nouns.map(|m| m.start).collect::<Vec<_>>()
+        );
+
+        // 前後から交互に取り出しても重複しない
+        let mut iter = tagger.wakati_iter(text);
+        assert_eq!(Some("すもも"), iter.next());
+        assert_eq!(Some("うち"), iter.next_back());
+        assert_eq!(
+            vec!["も", "もも", "も", "もも", "の"],
+            iter.collect::<Vec<_>>()
+        );
+
+        // イテレータはラティス以外の領域を確保しない
+        let lattice_allocs = count_allocs(|| drop(tagger.lattice(text)));
+        assert_eq!(lattice_allocs, count_allocs(|| drop(tagger.tokens(text))));
+        assert_eq!(
+            lattice_allocs,
+            count_allocs(|| drop(tagger.wakati_iter(text)))
+        );
+    }
+
+    fn surfaces<'a>(ms: &[Morpheme<'_, 'a>]) -> Vec<&'a str> {
+        ms.iter().map(|m| m.surface).collect()
+    }
+
+    fn features<'a>(ms: &[Morpheme<'a, '_>]) -> Vec<(usize, &'a str)> {
+        ms.iter().map(|m| (m.start, m.feature)).collect()
+    }
+
+    fn test_parse_nbest() {
+        let tagger = setup_tagger();
+
+        let text = "すもももももももものうち";
+        let best = tagger.parse(text);
+        let results = tagger.parse_nbest(text, 5);
+        assert_eq!(5, results.len());
+
+        // 1位はparse()の結果と一致する
+        assert_eq!(surfaces(&best), surfaces(&results[0].0));
+
+        for (i, (ms, cost)) in results.iter().enumerate() {
+            assert_eq!(text, surfaces(ms).concat());
+            if i > 0 {
+                let (ref prev_ms, prev_cost) = results[i - 1];
+                assert!(prev_cost <= *cost);
+                assert_ne!(features(prev_ms), features(ms));
+            }
+        }
+
+        assert!(tagger.parse_nbest(text, 0).is_empty());
+
+        // 未知語を含む場合も、同じ経路は2度現れない
+        for text in &["ｲーよりウ", "ABCあいうｶﾀｶﾅ123"] {
+            let paths: Vec<Vec<_>> = tagger
+                .parse_nbest(text, 10)
+                .iter()
+                .map(|(ms, _)| {
+                    ms.iter()
+                        .map(|m| (m.byte_range.clone(), m.word_id, m.kind))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(10, paths.len());
+            for (i, path) in paths.iter().enumerate() {
+                assert!(!paths[..i].contains(path), "{}: {:?}", text, path);
+            }
+        }
+    }
+
+    fn test_lattice() {
+        let tagger = setup_tagger();
+
+        let text = "すもも abc";
+        let lattice = tagger.lattice(text);
+        assert_eq!(text.len(), lattice.len()); // ラティス上の位置はバイト単位
+        assert_eq!(NodeKind::Bos, lattice.node(lattice.bos()).kind);
+        assert_eq!(NodeKind::Eos, lattice.node(lattice.eos()).kind);
+        assert_eq!("BOS/EOS", tagger.feature(lattice.node(lattice.eos())));
+
+        let best = tagger.parse(text);
+        let path = lattice.best_path();
+        assert_eq!(best.len(), path.len());
+        for (m, &idx) in best.iter().zip(path.iter()) {
+            let n = lattice.node(idx);
+            assert_eq!(m.byte_range, n.start..n.end());
+            assert_eq!(m.feature, tagger.feature(n));
+        }
+        assert_eq!(
+            tagger.parse_nbest(text, 1)[0].1,
+            lattice.node(lattice.eos()).cost
+        );
+
+        // "abc"は未知語処理によるノードのみ
+        assert!(!lattice.begin_nodes(10).is_empty());
+        for &idx in lattice.begin_nodes(10) {
+            assert_eq!(NodeKind::Unknown, lattice.node(idx).kind);
+        }
+        // 空白を読み飛ばして"すもも"が"abc"に連接する
+        assert!(lattice
+            .end_nodes(10)
+            .iter()
+            .any(|&idx| lattice.node(idx).end() == 9));
+    }
+
+    fn test_marginals() {
+        let tagger = setup_tagger();
+
+        let text = "すもももももももものうち";
+        let theta = 0.001;
+        let results = tagger.parse_with_marginals(text, theta);
+        assert_eq!(surfaces(&tagger.parse(text)), surfaces(&results));
+        for m in &results {
+            let prob = m.prob.unwrap();
+            assert!(0.0 < prob && prob <= 1.0 + 1e-9);
+        }
+
+        // 先頭位置から始まるノードの周辺確率の和は1になる
+        let lattice = tagger.lattice(text);
+        let marginals = tagger.marginals(&lattice, theta);
+        let sum: f64 = lattice.begin_nodes(0).iter().map(|&i| marginals[i]).sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+        assert!((marginals[lattice.eos()] - 1.0).abs() < 1e-9);
+
+        assert!(tagger.parse(text).iter().all(|m| m.prob.is_none()));
+
+        // 未知語の長さごとのノードとまとめ上げたノードは重複しないため、確率が分散しない
+        for text in &["ｲーより", "ABCあいうｶﾀｶﾅ123"] {
+            for m in tagger.parse_with_marginals(text, 1.0) {
+                assert!((m.prob.unwrap() - 1.0).abs() < 1e-6, "{}", m.surface);
+            }
+        }
+    }
+
+    fn test_parse_with_constraints() {
+        let tagger = setup_tagger();
+
+        let text = "すもももももももものうち";
+        // "すもも|も|もも|..."となる箇所を"すもも|もも|..."に強制する
+        let mut constraints = Constraints::new();
+        constraints.add_span(9..15, Some("名詞,一般"));
+        let results = tagger.parse_with_constraints(text, &constraints).unwrap();
+        assert_eq!(text, surfaces(&results).concat());
+        assert_eq!("もも", results[1].surface);
+        assert_eq!(3, results[1].start);
+        assert!(results[1].feature.starts_with("名詞,一般,"));
+
+        // "すもも"の途中を境界にする
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(6);
+        let results = tagger.parse_with_constraints(text, &constraints).unwrap();
+        assert_eq!(text, surfaces(&results).concat());
+        assert!(results.iter().any(|m| m.start == 2));
+
+        // パターンに一致する候補が無い範囲は未知語になる
+        let mut constraints = Constraints::new();
+        constraints.add_span(0..15, Some("動詞"));
+        let results = tagger.parse_with_constraints(text, &constraints).unwrap();
+        assert_eq!(text, surfaces(&results).concat());
+        assert_eq!("すもももも", results[0].surface);
+
+        // 不正な制約はエラーになる
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(1);
+        assert!(tagger.parse_with_constraints(text, &constraints).is_err());
+        let mut constraints = Constraints::new();
+        constraints.add_span(3..3, None);
+        assert!(tagger.parse_with_constraints(text, &constraints).is_err());
+        let mut constraints = Constraints::new();
+        constraints.add_span(0..6, None);
+        constraints.add_span(3..9, None);
+        assert!(tagger.parse_with_constraints(text, &constraints).is_err());
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(text.len() + 3);
+        assert!(tagger.parse_with_constraints(text, &constraints).is_err());
+    }
+
+    fn test_user_dic() {
+        let mut tagger = setup_tagger();
+        let user_dic = UserDic::from_csv(Path::new(TEST_USER_DIC_PATH), ",", "UTF-8").unwrap();
+        assert_eq!(2, user_dic.len());
+        tagger.set_user_dic(Some(user_dic)).unwrap();
+
+        let text = "すもももももももものうち";
+        let results = tagger.parse(text);
+        assert_eq!(text, surfaces(&results).concat());
+        assert_eq!("すもももも", results[0].surface);
+        assert_eq!(
+            "名詞,固有名詞,一般,*,*,*,すもももも,スモモモモ,スモモモモ",
+            results[0].feature
+        );
+        let lattice = tagger.lattice(text);
+        assert_eq!(NodeKind::User, lattice.node(lattice.best_path()[0]).kind);
+
+        tagger.set_user_dic(None).unwrap();
+        assert_eq!("すもも", tagger.parse(text)[0].surface);
+        // ユーザー辞書を外す前のラティスのノードの素性は得られない
+        assert_eq!("", tagger.feature(lattice.node(lattice.best_path()[0])));
+
+        // 区切り文字を指定できる
+        let source = build::DicSource::Bytes(
+            "user.tsv".to_string(),
+            "すもももも\t1285\t1285\t-5000\t名詞\t固有名詞\n"
+                .as_bytes()
+                .to_vec(),
+        );
+        let user_dic = UserDic::from_source(&source, "\t", "UTF-8").unwrap();
+        tagger.set_user_dic(Some(user_dic)).unwrap();
+        let results = tagger.parse(text);
+        assert_eq!("すもももも", results[0].surface);
+        assert_eq!("名詞\t固有名詞", results[0].feature);
+        // 差し替える前のユーザー辞書のノードの素性も得られない
+        assert_eq!("", tagger.feature(lattice.node(lattice.best_path()[0])));
+
+        // 連接コスト表の範囲外の文脈IDは拒否される
+        let source = build::DicSource::Bytes(
+            "invalid_user.csv".to_string(),
+            "もも,9999,9999,0,名詞,一般\n".as_bytes().to_vec(),
+        );
+        let user_dic = UserDic::from_source(&source, ",", "UTF-8").unwrap();
+        assert!(tagger.set_user_dic(Some(user_dic)).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    fn test_mmap() {
+        let tagger = setup_tagger();
+        let mapped = Tagger::new_mmap(Path::new(TEST_DIC_DST_PATH)).unwrap();
+        let text = "すもももももももものうち";
+        assert_eq!(features(&tagger.parse(text)), features(&mapped.parse(text)));
+        // 複製したインスタンスはマップした領域を共有する
+        let cloned = mapped.clone();
+        drop(mapped);
+        assert_eq!(features(&tagger.parse(text)), features(&cloned.parse(text)));
+    }
+
+    fn test_corrupted_dic() {
+        use byteorder::WriteBytesExt;
+
+        let dic_dir = Path::new(TEST_DIC_DST_PATH);
+        let matrix = std::fs::read(dic_dir.join("matrix.bin")).unwrap();
+        let open = |bytes: &[u8]| DicFile::from_bytes("matrix.bin", bytes.to_vec());
+        assert!(open(&matrix).is_ok());
+
+        let err = open(&matrix[..matrix.len() - 1]).err().unwrap();
+        assert!(err.to_string().contains("size mismatch"), "{}", err);
+
+        let mut corrupted = matrix.clone();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        let err = open(&corrupted).err().unwrap();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+
+        for version in [0u32, 1, 99] {
+            let mut other = matrix.clone();
+            other[12..16].copy_from_slice(&version.to_le_bytes());
+            let err = open(&other).err().unwrap();
+            assert!(err.to_string().contains("unsupported"), "{}", err);
+        }
+
+        // 配列は要素のサイズ境界に揃えて書き込まれる
+        let mut writer = crate::storage::DicWriter::new();
+        writer.write_u8(7).unwrap();
+        writer.align::<i32>();
+        writer.write_i32::<byteorder::LE>(-2).unwrap();
+        let bytes = writer.into_bytes();
+        assert_eq!(crate::storage::HEADER_SIZE + 8, bytes.len());
+        let mut file = DicFile::from_bytes("padded", bytes).unwrap();
+        assert_eq!(7, file.read_value::<u8>().unwrap());
+        assert_eq!(-2, file.read_value::<i32>().unwrap());
+        assert_eq!(0, file.remaining());
+    }
+
+    fn test_bundle() {
+        let mut bytes = Vec::new();
+        build::DictionaryBuilder::from_dir(Path::new(TEST_DIC_SRC_PATH), "UTF-8")
+            .unwrap()
+            .build_bundle(&mut bytes)
+            .unwrap();
+        let bundle_path = Path::new(TEST_DIC_DST_PATH).join("igo.bundle");
+        std::fs::write(&bundle_path, &bytes).unwrap();
+
+        let tagger = setup_tagger();
+        let text = "すもももももももものうち";
+        let expected = features(&tagger.parse(text));
+        let from_file = Tagger::from_bundle_file(&bundle_path).unwrap();
+        assert_eq!(expected, features(&from_file.parse(text)));
+
+        let from_bytes = Tagger::from_bundle(&bytes).unwrap();
+        assert_eq!(expected, features(&from_bytes.parse(text)));
+
+        let mut bundle = Bundle::new(&bytes[..]).unwrap();
+        assert_eq!(7, bundle.file_names().count());
+        assert!(bundle.open("unknown").is_err());
+        assert!(Bundle::new(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut files = build::DictionaryBuilder::from_dir(Path::new(TEST_DIC_SRC_PATH), "UTF-8")
+            .unwrap()
+            .build()
+            .unwrap();
+        files.remove("word.dat");
+        let err = build::write_bundle(&files, Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("word.dat"));
+    }
+
+    #[allow(deprecated)]
+    fn test_deprecated_builders() {
+        use crate::dictionary::build::{matrix, CharCategory, WordDic};
+
+        let src_dir = Path::new(TEST_DIC_SRC_PATH);
+        let out_dir = std::env::temp_dir().join(format!("igo-deprecated-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let mut wd = WordDic::new(src_dir, "UTF-8", &out_dir, ",".to_string());
+        wd.build_word_id_map().unwrap();
+        wd.build_word_info().unwrap();
+        matrix::build(src_dir, &out_dir).unwrap();
+        CharCategory::new(src_dir, "UTF-8", &out_dir)
+            .build()
+            .unwrap();
+        for name in crate::storage::DIC_FILES {
+            let expected = std::fs::read(Path::new(TEST_DIC_DST_PATH).join(name)).unwrap();
+            let actual = std::fs::read(out_dir.join(name)).unwrap();
+            assert!(actual == expected, "{} differs", name);
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    fn test_dictionary_builder() {
+        use crate::dictionary::build::{BuildStep, DicSource, DictionaryBuilder};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let src_dir = Path::new(TEST_DIC_SRC_PATH);
+        let source = |name: &str| {
+            let file = std::fs::File::open(src_dir.join(name)).unwrap();
+            DicSource::from_reader(name, file).unwrap()
+        };
+        let mut builder = DictionaryBuilder::new();
+        let mut csv_files: Vec<_> = std::fs::read_dir(src_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".csv"))
+            .collect();
+        csv_files.sort();
+        for name in &csv_files {
+            builder.add_words(source(name), "UTF-8");
+        }
+        builder.set_unk_def(source("unk.def"), "UTF-8");
+        builder.set_char_def(source("char.def"), "UTF-8");
+        builder.set_matrix(source("matrix.def"));
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let steps_ref = steps.clone();
+        builder.set_progress(move |step| steps_ref.borrow_mut().push(step));
+
+        let mut files = builder.build().unwrap();
+        assert_eq!(
+            vec![
+                BuildStep::WordTrie,
+                BuildStep::WordDic,
+                BuildStep::Matrix,
+                BuildStep::CharCategory
+            ],
+            *steps.borrow()
+        );
+        for name in crate::storage::DIC_FILES {
+            let expected = std::fs::read(Path::new(TEST_DIC_DST_PATH).join(name)).unwrap();
+            assert!(files[*name] == expected, "{} differs", name);
+        }
+        let tagger = Tagger::load_from_dir(&mut files).unwrap();
+        let text = "すもももももももものうち";
+        assert_eq!(
+            features(&setup_tagger().parse(text)),
+            features(&tagger.parse(text))
+        );
+
+        let mut builder = DictionaryBuilder::new();
+        builder.set_unk_def(source("unk.def"), "UTF-8");
+        assert!(builder.build().is_err());
+    }
+
+    fn test_validate_dic() {
+        use crate::dictionary::build::{DicSource, DictionaryBuilder, Severity};
+
+        let src_dir = Path::new(TEST_DIC_SRC_PATH);
+        let mut builder = DictionaryBuilder::from_dir(src_dir, "UTF-8").unwrap();
+        let report = builder.validate().unwrap();
+        assert!(report.issues.is_empty(), "{}", report);
+
+        let words = "すもも,1285,1285,7546,名詞,一般,*,*,*,*,すもも,スモモ,スモモ\n\
+                     ばなな,2000,1285,100,名詞,一般,*,*,*,*,ばなな,バナナ,バナナ\n\
+                     りんご,1285,1285,40000,名詞,一般,*,*,*,*,りんご,リンゴ,リンゴ\n\
+                     みかん,1285,1285,100,名詞,一般\n\
+                     ぶどう,x,1285\n";
+        builder.add_words(
+            DicSource::Bytes("extra.csv".to_string(), words.as_bytes().to_vec()),
+            "UTF-8",
+        );
+        let report = builder.validate().unwrap();
+        assert!(report.has_errors());
+        let issues: Vec<_> = report
+            .issues
+            .iter()
+            .map(|i| (i.severity, i.path.to_str().unwrap(), i.line_number))
+            .collect();
+        assert_eq!(
+            vec![
+                (Severity::Warning, "extra.csv", 1),
+                (Severity::Error, "extra.csv", 2),
+                (Severity::Error, "extra.csv", 3),
+                (Severity::Warning, "extra.csv", 4),
+                (Severity::Error, "extra.csv", 5),
+            ],
+            issues
+        );
+        assert!(report.issues[0].message.contains("Noun.tiny.csv:1"));
+        assert!(builder.build().is_err());
+    }
+
+    fn test_homographs() {
+        use crate::dictionary::build::{DicSource, DictionaryBuilder};
+
+        let words = "すもも,1285,1285,7546,名詞,一般,*,*,*,*,すもも,スモモ2,スモモ2\n";
+        let mut builder =
+            DictionaryBuilder::from_dir(Path::new(TEST_DIC_SRC_PATH), "UTF-8").unwrap();
+        builder.add_words(
+            DicSource::Bytes("extra.csv".to_string(), words.as_bytes().to_vec()),
+            "UTF-8",
+        );
+        builder.set_keep_homographs(true);
+        let tagger = Tagger::load_from_dir(&mut builder.build().unwrap()).unwrap();
+
+        let m = &tagger.parse("すもも")[0];
+        assert_eq!("名詞,一般,*,*,*,*,すもも,スモモ,スモモ", m.feature);
+        assert_eq!(
+            vec![
+                "名詞,一般,*,*,*,*,すもも,スモモ,スモモ",
+                "名詞,一般,*,*,*,*,すもも,スモモ2,スモモ2"
+            ],
+            tagger.homographs(m.surface, m.left_id, m.right_id)
+        );
+        assert_eq!(
+            1,
+            setup_tagger()
+                .homographs("すもも", m.left_id, m.right_id)
+                .len()
+        );
+        assert!(tagger
+            .homographs("すもも", m.left_id + 1, m.right_id)
+            .is_empty());
+        assert!(tagger
+            .homographs("ぶどう", m.left_id, m.right_id)
+            .is_empty());
+    }
+
+    fn test_word_dic_lookup() {
+        let tagger = setup_tagger();
+        let wdc = tagger.word_dic();
+
+        let entries = wdc.lookup("すもも");
+        assert_eq!(1, entries.len());
+        assert_eq!("すもも", entries[0].surface);
+        assert_eq!("名詞,一般,*,*,*,*,すもも,スモモ,スモモ", entries[0].feature);
+        assert_eq!(
+            (1285, 1285, 7546),
+            (entries[0].left_id, entries[0].right_id, entries[0].cost)
+        );
+        let m = &tagger.parse("すもも")[0];
+        assert_eq!(m.word_id, entries[0].word_id);
+        assert!(wdc.lookup("すもも肉").is_empty());
+        assert!(wdc.lookup("").is_empty());
+        assert!(wdc.lookup("\x02DEFAULT").is_empty());
+        // 予約された文字を含む表層形は一致しない
+        assert!(wdc.lookup("\u{1}").is_empty());
+        assert!(wdc.lookup("\u{1}\u{1}").is_empty());
+        assert!(wdc.lookup("すもも\0").is_empty());
+        assert!(wdc.common_prefix("\u{1}\u{1}").is_empty());
+        assert!(tagger.homographs("\u{1}\u{1}", 0, 0).is_empty());
+
+        let surfaces: Vec<_> = wdc
+            .common_prefix("ももんがあです")
+            .iter()
+            .map(|e| e.surface)
+            .collect();
+        assert_eq!(vec!["も", "もも", "ももんが", "ももんがあ"], surfaces);
+        assert!(wdc.common_prefix("").is_empty());
+    }
+
+    fn test_in_memory_dir() {
+        let dic_dir = Path::new(TEST_DIC_DST_PATH);
+        let files: Vec<(&str, Vec<u8>)> = crate::storage::DIC_FILES
+            .iter()
+            .map(|name| (*name, std::fs::read(dic_dir.join(name)).unwrap()))
+            .collect();
+        let text = "すもももももももものうち";
+        let system = setup_tagger();
+        let expected = features(&system.parse(text));
+
+        let mut map: HashMap<String, Vec<u8>> = files
+            .iter()
+            .map(|(name, data)| (name.to_string(), data.clone()))
+            .collect();
+        let tagger = Tagger::load_from_dir(&mut map).unwrap();
+        assert_eq!(expected, features(&tagger.parse(text)));
+
+        #[cfg(feature = "zip")]
+        {
+            use std::io::Write;
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for (name, data) in &files {
+                zip.start_file(*name, Default::default()).unwrap();
+                zip.write_all(data).unwrap();
+            }
+            let mut archive = zip::ZipArchive::new(zip.finish().unwrap()).unwrap();
+            let tagger = Tagger::load_from_dir(&mut archive).unwrap();
+            assert_eq!(expected, features(&tagger.parse(text)));
+
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for (name, data) in &files {
+                zip.start_file(format!("ipadic/{}", name), Default::default())
+                    .unwrap();
+                zip.write_all(data).unwrap();
+            }
+            let archive = zip::ZipArchive::new(zip.finish().unwrap()).unwrap();
+            let mut dir = crate::SubDir::new(archive, "ipadic");
+            let tagger = Tagger::load_from_dir(&mut dir).unwrap();
+            assert_eq!(expected, features(&tagger.parse(text)));
+        }
+
+        #[cfg(feature = "tar")]
+        {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (name, data) in &files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder
+                    .append_data(&mut header, format!("./ipadic/{}", name), &data[..])
+                    .unwrap();
+            }
+            let tar_bytes = builder.into_inner().unwrap();
+            let archive = crate::TarArchive::new(&tar_bytes[..]).unwrap();
+            assert!(archive.file_names().all(|name| name.starts_with("ipadic/")));
+            let mut dir = crate::SubDir::new(archive, "ipadic/");
+            let tagger = Tagger::load_from_dir(&mut dir).unwrap();
+            assert_eq!(expected, features(&tagger.parse(text)));
+            assert!(dir.into_inner().open("word2id").is_err());
+
+            #[cfg(feature = "gzip")]
+            {
+                use std::io::Write;
+                let mut gz =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                gz.write_all(&tar_bytes).unwrap();
+                let archive = crate::TarArchive::from_gz(&gz.finish().unwrap()[..]).unwrap();
+                let mut dir = crate::SubDir::new(archive, "ipadic");
+                let tagger = Tagger::load_from_dir(&mut dir).unwrap();
+                assert_eq!(expected, features(&tagger.parse(text)));
+                assert!(crate::TarArchive::from_gz(&tar_bytes[..]).is_err());
+            }
+        }
+    }
+
+    fn test_parse_batch() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Tagger>();
+
+        let tagger = std::sync::Arc::new(setup_tagger());
+        let texts = ["すもももももももものうち", "", "もものうち"];
+        let results = tagger.parse_batch(&texts);
+        assert_eq!(texts.len(), results.len());
+        for (text, ms) in texts.iter().zip(&results) {
+            assert_eq!(features(&tagger.parse(text)), features(ms));
+        }
+
+        let handles: Vec<_> = texts
+            .iter()
+            .map(|&text| {
+                let tagger = std::sync::Arc::clone(&tagger);
+                std::thread::spawn(move || features(&tagger.parse(text)).len())
+            })
+            .collect();
+        for (handle, ms) in handles.into_iter().zip(&results) {
+            assert_eq!(ms.len(), handle.join().unwrap());
+        }
+    }
+
+    fn test_parse_into() {
+        let tagger = setup_tagger();
+        let mut ctx = ParseContext::new();
+        let mut out = Vec::new();
+        // 長いテキストの後に短いテキストを解析しても、前の結果が残らない
+        for text in &[
+            "すもももももももものうち",
+            "もも",
+            "",
+            "すもももももももものうち",
+        ] {
+            tagger.parse_into(&mut ctx, text, &mut out);
+            assert_eq!(features(&tagger.parse(text)), features(&out));
+            assert_eq!(text.len(), ctx.lattice().len());
+        }
+
+        // 作業領域の容量が足りていれば、領域を確保しない
+        let text = "すもももももももものうち";
+        assert_eq!(
+            0,
+            count_allocs(|| tagger.parse_into(&mut ctx, text, &mut out))
+        );
+    }
+
+    fn test_feature_accessors() {
+        let mut tagger = setup_tagger();
+        let results = tagger.parse("すもももももももものうち");
+        let m = &results[6];
+        assert_eq!(Some("名詞"), m.pos().as_deref());
+        assert_eq!(Some("非自立"), m.pos_detail(1).as_deref());
+        assert_eq!(Some("副詞可能"), m.pos_detail(2).as_deref());
+        assert_eq!(None, m.pos_detail(3));
+        assert_eq!(None, m.pos_detail(4));
+        assert_eq!(None, m.conjugation_type());
+        assert_eq!(Some("うち"), m.base_form().as_deref());
+        assert_eq!(Some("ウチ"), m.reading().as_deref());
+        assert_eq!(Some("ウチ"), m.to_owned().pronunciation().as_deref());
+
+        // クォートで囲まれた項目はカンマを含められる
+        let fields: Vec<_> = split_feature(r#"記号,"1,000","""",*,"a""b"#).collect();
+        assert_eq!(vec!["記号", "1,000", "\"", "*", "a\"b"], fields);
+
+        // IPADICの素性にはUniDicの読み・発音の位置の項目が無い
+        tagger.set_feature_schema(FeatureSchema::UNIDIC);
+        let results = tagger.parse("うち");
+        assert_eq!(Some("名詞"), results[0].pos().as_deref());
+        assert_eq!(None, results[0].reading());
+        assert_eq!(None, results[0].pronunciation());
+        assert_eq!(&FeatureSchema::UNIDIC, &results[0].to_owned().schema);
+    }
+
+    fn test_token_stream() {
+        let tagger = setup_tagger();
+        let text = "すもももももももものうち。もも！\n😀すもも\nもも";
+        let tokens: Vec<MorphemeBuf> = tagger
+            .token_stream(text.as_bytes())
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let expected: Vec<_> = [
+            "すもももももももものうち。",
+            "もも！",
+            "\n😀すもも\n",
+            "もも",
+        ]
+        .iter()
+        .flat_map(|s| tagger.parse(s).into_iter().map(|m| m.feature))
+        .collect();
+        assert_eq!(
+            expected,
+            tokens.iter().map(|m| &*m.feature).collect::<Vec<_>>()
+        );
+        for m in &tokens {
+            // 位置は入力全体の先頭からの位置
+            assert_eq!(m.surface, &text[m.byte_range.clone()]);
+            assert_eq!(
+                m.char_range.start,
+                text[..m.byte_range.start].chars().count()
+            );
+            assert_eq!(m.start, text[..m.byte_range.start].encode_utf16().count());
+        }
+
+        // 文の終わりが無い長いテキストは最大長で区切られる
+        let mut stream = tagger.token_stream("すもももももももものうち".as_bytes());
+        stream.set_max_chunk_len(10);
+        let tokens: Vec<MorphemeBuf> = stream.collect::<std::io::Result<_>>().unwrap();
+        assert_eq!(
+            "すもももももももものうち",
+            tokens.iter().map(|m| &*m.surface).collect::<String>()
+        );
+        assert!(tokens
+            .iter()
+            .all(|m| m.byte_range.end <= 9 || m.byte_range.start >= 9));
+
+        let mut stream = tagger.token_stream(&b"\xffabc"[..]);
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    fn test_formatter() {
+        let tagger = setup_tagger();
+        let text = "すもも ABC";
+        let results = tagger.parse(text);
+        let format = |name: &str| {
+            Formatter::preset(name)
+                .unwrap()
+                .format(&tagger, text, &results)
+        };
+
+        assert_eq!(
+            "すもも\t名詞,一般,*,*,*,*,すもも,スモモ,スモモ\nABC\t名詞,一般,*,*,*,*,*\nEOS\n",
+            format("default")
+        );
+        assert_eq!("すもも ABC \n", format("wakati"));
+        assert_eq!("スモモ ABC\n", format("yomi"));
+        assert_eq!(
+            "すもも\tスモモ\tすもも\t名詞-一般\t*\t*\nABC\tABC\tABC\t名詞-一般\t\t\nEOS\n",
+            format("chasen")
+        );
+        let dump = format("dump");
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[0].starts_with("0  BOS/EOS 0 0 "));
+        assert!(lines[2].starts_with("2 ABC 名詞,一般,*,*,*,*,* 10 13 "));
+        assert!(lines[2].ends_with(&format!(" {}", results[1].cost)));
+        // EOSの累積コストにはEOSとの連接コストも含まれる
+        let lattice = tagger.lattice(text);
+        let eos_cost = lattice.node(lattice.eos()).cost;
+        assert_ne!(results[1].cost, eos_cost);
+        assert!(lines[3].ends_with(&format!(" {} {}", eos_cost - results[1].cost, eos_cost)));
+        assert!(Formatter::preset("unknown").is_err());
+
+        // ラティスを渡すと、dumpは最適経路以外のノードも出力する
+        let dump = Formatter::preset("dump")
+            .unwrap()
+            .format_lattice(&tagger, text, &lattice);
+        assert_eq!(lattice.nodes().len(), dump.lines().count());
+        assert!(dump
+            .lines()
+            .last()
+            .unwrap()
+            .ends_with(&format!(" {}", eos_cost)));
+        // ラティスを渡すと、周辺確率も出力される
+        let mut formatter = Formatter::new("%m %pP\n", "EOS %pP\n").unwrap();
+        formatter.set_theta(1.0);
+        let marginals = tagger.parse_with_marginals(text, 1.0);
+        assert_eq!(
+            format!(
+                "すもも {:.6}\nABC {:.6}\nEOS 1.000000\n",
+                marginals[0].prob.unwrap(),
+                marginals[1].prob.unwrap()
+            ),
+            formatter.format_lattice(&tagger, text, &lattice)
+        );
+        let mut formatter = Formatter::new("%pb%m\n", "%pbEOS\n").unwrap();
+        assert_eq!(
+            "*すもも\n*ABC\n*EOS\n",
+            formatter.format_lattice(&tagger, text, &lattice)
+        );
+        formatter.set_all_morphs(true);
+        let all = formatter.format_lattice(&tagger, text, &lattice);
+        let best: Vec<_> = all.lines().filter(|l| l.starts_with('*')).collect();
+        assert_eq!(vec!["*すもも", "*ABC", "*EOS"], best);
+        assert!(all.lines().any(|l| l.starts_with(' ')));
+
+        let mut formatter = Formatter::new("[%m|%f[0,1]|%F/[1,2]|%ps-%pe|%%]", "<%L>").unwrap();
+        formatter.set_bos_format("<%S>").unwrap();
+        assert_eq!(
+            "<すもも ABC>[すもも|名詞,一般|一般|0-9|%][ABC|名詞,一般|一般|10-13|%]<13>",
+            formatter.format(&tagger, text, &results)
+        );
+        assert!(Formatter::new("%x", "").is_err());
+        assert!(Formatter::new("%f[a]", "").is_err());
+    }
+
+    fn test_conllu() {
+        let tagger = setup_tagger();
+        let text = "すもも ABC";
+        let results = tagger.parse(text);
+
+        let mut writer = ConlluWriter::new(Vec::new(), ConlluMapping::ipadic());
+        writer.write_sentence(text, &results).unwrap();
+        writer.write_sentence("", &[]).unwrap();
+        writer.write_sentence("ABC", &tagger.parse("ABC")).unwrap();
+        assert_eq!(
+            "# sent_id = 1\n# text = すもも ABC\n\
+             1\tすもも\tすもも\tNOUN\t名詞-一般\t_\t_\t_\t_\t_\n\
+             2\tABC\tABC\tNOUN\t名詞-一般\t_\t_\t_\t_\t_\n\n\
+             # sent_id = 2\n# text = ABC\n\
+             1\tABC\tABC\tNOUN\t名詞-一般\t_\t_\t_\t_\t_\n\n",
+            String::from_utf8(writer.into_inner()).unwrap()
+        );
+
+        let mut mapping = ConlluMapping::new();
+        assert_eq!("X", mapping.upos("名詞,一般"));
+        mapping.add_upos("名詞", "NOUN");
+        mapping.add_upos("名詞,固有名詞", "PROPN");
+        mapping.add_feats("名詞,*,人名", "NameType=Prs");
+        mapping.add_feats("名詞,固有名詞", "Foreign=No|NameType=Prs");
+        assert_eq!("PROPN", mapping.upos("名詞,固有名詞,人名,姓"));
+        assert_eq!("NOUN", mapping.upos("名詞,一般"));
+        assert_eq!(
+            Some("Foreign=No|NameType=Prs".to_string()),
+            mapping.feats("名詞,固有名詞,人名,姓")
+        );
+        assert_eq!(None, mapping.feats("名詞,一般"));
+        assert_eq!(
+            "AUX",
+            ConlluMapping::unidic().upos("助動詞,*,*,*,助動詞-タ")
+        );
+        assert_eq!(
+            Some("Tense=Past".to_string()),
+            ConlluMapping::unidic().feats("助動詞,*,*,*,助動詞-タ,終止形-一般")
+        );
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&results[0]).unwrap();
+            let buf: MorphemeBuf = serde_json::from_str(&json).unwrap();
+            assert_eq!("すもも", buf.surface);
+            assert_eq!(results[0].byte_range, buf.byte_range);
+            assert_eq!(results[0].kind, buf.kind);
+            assert_eq!(Some("すもも".into()), buf.base_form());
+        }
     }
 }
@@ -0,0 +1,197 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use getopts::Options;
+
+use igo::dictionary::build::{AppError, AppResult};
+use igo::dictionary::UserDic;
+use igo::{ConlluMapping, ConlluWriter, FeatureSchema, Formatter, Morpheme, Tagger};
+
+/// 辞書の場所を指定する環境変数
+const DIC_ENV: &str = "IGO_DIC";
+
+fn print_usage(program: &str, opts: Options) {
+    println!("{}", opts.usage(&format!(
+        "Usage:\n {} [options] <dictionary> [files...]\n\n\
+         <dictionary> is a binary dictionary directory or bundle file. It can be omitted \
+         when -d or the {} environment variable is given.\n\
+         Reads the files (or stdin) line by line unless -t is given.", program, DIC_ENV)));
+}
+
+/// 解析結果の出力先
+enum Output<W: Write> {
+    Format(Formatter, W),
+    #[cfg(feature = "serde")]
+    Json(W),
+    Conllu(ConlluWriter<W>)
+}
+
+impl<W: Write> Output<W> {
+    /// 最適な解析結果を書き出す. 書式指定の場合はラティスから書き出す(`dump`では全てのノードを出力する)
+    fn write_best(&mut self, tagger: &Tagger, text: &str) -> io::Result<()> {
+        match *self {
+            Output::Format(ref formatter, ref mut writer) =>
+                formatter.write_lattice(writer, tagger, text, &tagger.lattice(text)),
+            _ => self.write(tagger, text, &tagger.parse(text))
+        }
+    }
+
+    fn write(&mut self, tagger: &Tagger, text: &str, morphemes: &[Morpheme]) -> io::Result<()> {
+        match *self {
+            Output::Format(ref formatter, ref mut writer) => formatter.write(writer, tagger, text, morphemes),
+            #[cfg(feature = "serde")]
+            Output::Json(ref mut writer) => {
+                serde_json::to_writer(&mut *writer, morphemes)?;
+                writeln!(writer)
+            }
+            Output::Conllu(ref mut writer) => writer.write_sentence(text, morphemes)
+        }
+    }
+}
+
+fn load_tagger(path: &Path) -> io::Result<Tagger> {
+    if path.is_file() {
+        Tagger::from_bundle_file(path)
+    } else {
+        Tagger::new(path)
+    }
+}
+
+fn tagger_cli() -> AppResult<i32> {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("t", "text", "analyze TEXT instead of reading the input.", "TEXT");
+    opts.optopt("d", "dicdir", "use the binary dictionary in DIR (or bundle file).", "DIR");
+    opts.optopt("u", "userdic", "use the user dictionary FILE (CSV).", "FILE");
+    opts.optopt("", "userdic-delimiter", "set the delimiter of the user dictionary (default: ',').", "DELIM");
+    opts.optopt("", "userdic-encoding", "set the encoding of the user dictionary (default: UTF-8).", "ENC");
+    opts.optopt("O", "output-format-type", "set the output format: default, wakati, yomi, chasen, dump, json or conllu.", "TYPE");
+    opts.optopt("", "node-format", "set the user-defined node format (MeCab style).", "STR");
+    opts.optopt("", "eos-format", "set the user-defined EOS format (MeCab style).", "STR");
+    opts.optopt("s", "schema", "set the feature schema: ipadic, naist-jdic or unidic.", "NAME");
+    opts.optopt("N", "nbest", "output the N best results.", "N");
+    opts.optflag("v", "verbose", "enable verbose mode.");
+    opts.optflag("", "help", "show this usage message.");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage(&program, opts);
+            return Ok(1);
+        }
+    };
+    if matches.opt_present("help") {
+        print_usage(&program, opts);
+        return Ok(0);
+    }
+    if matches.opt_present("verbose") {
+        simple_logger::init().unwrap();
+    }
+
+    let mut free = matches.free.clone().into_iter();
+    let dic_dir = match matches.opt_str("d").or_else(|| env::var(DIC_ENV).ok()).or_else(|| free.next()) {
+        Some(s) => PathBuf::from(s),
+        None => {
+            print_usage(&program, opts);
+            return Ok(1);
+        }
+    };
+    let files: Vec<String> = free.collect();
+
+    let (schema, mapping) = match matches.opt_str("s").as_deref() {
+        None | Some("ipadic") => (FeatureSchema::IPADIC, ConlluMapping::ipadic()),
+        Some("naist-jdic") => (FeatureSchema::NAIST_JDIC, ConlluMapping::ipadic()),
+        Some("unidic") => (FeatureSchema::UNIDIC, ConlluMapping::unidic()),
+        Some(s) => return Err(AppError::from(format!("unknown feature schema: {}", s)))
+    };
+    let nbest = match matches.opt_str("N") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(AppError::from(format!("invalid number of results: {}", s)))
+        },
+        None => 1
+    };
+
+    if nbest > 1 && matches.opt_str("O").as_deref() == Some("conllu") {
+        return Err(AppError::from("CoNLL-U output does not support N-best results"));
+    }
+
+    let mut tagger = load_tagger(&dic_dir)?;
+    tagger.set_feature_schema(schema);
+    if let Some(path) = matches.opt_str("u") {
+        let encoding = matches.opt_str("userdic-encoding").unwrap_or_else(|| "UTF-8".to_string());
+        let delimiter = matches.opt_str("userdic-delimiter").unwrap_or_else(|| ",".to_string());
+        tagger.set_user_dic(Some(UserDic::from_csv(Path::new(&path), &delimiter, &encoding)?))?;
+    }
+
+    let stdout = io::stdout();
+    let writer = BufWriter::new(stdout.lock());
+    let mut output = match matches.opt_str("O").as_deref() {
+        #[cfg(feature = "serde")]
+        Some("json") => Output::Json(writer),
+        #[cfg(not(feature = "serde"))]
+        Some("json") => return Err(AppError::from("JSON output requires the `serde` feature")),
+        Some("conllu") => Output::Conllu(ConlluWriter::new(writer, mapping)),
+        preset => {
+            let mut formatter = Formatter::preset(preset.unwrap_or("default"))?;
+            if let Some(node_format) = matches.opt_str("node-format") {
+                formatter = Formatter::new(&node_format, "EOS\n")?;
+            }
+            if let Some(eos_format) = matches.opt_str("eos-format") {
+                formatter.set_eos_format(&eos_format)?;
+            }
+            Output::Format(formatter, writer)
+        }
+    };
+
+    let mut parse_line = |line: &str| -> io::Result<()> {
+        let line = line.trim_end_matches('\r');
+        if nbest == 1 {
+            output.write_best(&tagger, line)
+        } else {
+            for (morphemes, _) in tagger.parse_nbest(line, nbest) {
+                output.write(&tagger, line, &morphemes)?;
+            }
+            Ok(())
+        }
+    };
+
+    if let Some(text) = matches.opt_str("t") {
+        for line in text.lines() {
+            parse_line(line)?;
+        }
+    } else if files.is_empty() {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            parse_line(&line?)?;
+        }
+    } else {
+        for path in &files {
+            for line in BufReader::new(File::open(path)?).lines() {
+                parse_line(&line?)?;
+            }
+        }
+    }
+
+    match output {
+        Output::Format(_, mut writer) => writer.flush()?,
+        #[cfg(feature = "serde")]
+        Output::Json(mut writer) => writer.flush()?,
+        Output::Conllu(writer) => writer.into_inner().flush()?
+    }
+    Ok(0)
+}
+
+fn main() {
+    match tagger_cli() {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,5 +1,12 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::feature::FeatureSchema;
+use crate::lattice::NodeKind;
+
 /// 形態素
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Morpheme<'tagger, 'text> {
     /// 形態素の表層形
     pub surface: &'text str,
@@ -7,8 +14,39 @@ pub struct Morpheme<'tagger, 'text> {
     /// 形態素の素性
     pub feature: &'tagger str,
 
-    /// テキスト内での形態素の出現開始位置
-    pub start: usize
+    /// テキスト内での形態素の出現開始位置(UTF-16単位)
+    pub start: usize,
+
+    /// テキスト内での形態素の範囲(UTF-8のバイト単位). `&text[byte_range]`で表層形を取り出せる
+    pub byte_range: Range<usize>,
+
+    /// テキスト内での形態素の範囲(`char`単位)
+    pub char_range: Range<usize>,
+
+    /// 形態素の周辺確率(`Tagger::parse_with_marginals`で解析した場合のみ)
+    pub prob: Option<f64>,
+
+    /// 単語ID. 形態素の由来(`kind`)ごとの辞書内でのID
+    pub word_id: i32,
+
+    /// 左文脈ID
+    pub left_id: i16,
+
+    /// 右文脈ID
+    pub right_id: i16,
+
+    /// 単語のコスト
+    pub word_cost: i32,
+
+    /// 文頭から形態素までの最小の累積コスト
+    pub cost: i32,
+
+    /// 形態素の由来
+    pub kind: NodeKind,
+
+    /// 素性の項目の並び
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub schema: &'tagger FeatureSchema
 }
 
 impl<'tagger, 'text> Morpheme<'tagger, 'text> {
@@ -16,13 +54,29 @@ impl<'tagger, 'text> Morpheme<'tagger, 'text> {
         MorphemeBuf {
             surface: self.surface.to_owned(),
             feature: self.feature.to_owned(),
-            start: self.start
+            start: self.start,
+            byte_range: self.byte_range.clone(),
+            char_range: self.char_range.clone(),
+            prob: self.prob,
+            word_id: self.word_id,
+            left_id: self.left_id,
+            right_id: self.right_id,
+            word_cost: self.word_cost,
+            cost: self.cost,
+            kind: self.kind,
+            schema: *self.schema
         }
     }
+
+    /// 未知語処理により生成された形態素かどうか
+    pub fn is_unknown(&self) -> bool {
+        self.kind == NodeKind::Unknown
+    }
 }
 
 /// 形態素
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MorphemeBuf {
     /// 形態素の表層形
     pub surface: String,
@@ -30,6 +84,47 @@ pub struct MorphemeBuf {
     /// 形態素の素性
     pub feature: String,
 
-    /// テキスト内での形態素の出現開始位置
-    pub start: usize
+    /// テキスト内での形態素の出現開始位置(UTF-16単位)
+    pub start: usize,
+
+    /// テキスト内での形態素の範囲(UTF-8のバイト単位). `&text[byte_range]`で表層形を取り出せる
+    pub byte_range: Range<usize>,
+
+    /// テキスト内での形態素の範囲(`char`単位)
+    pub char_range: Range<usize>,
+
+    /// 形態素の周辺確率(`Tagger::parse_with_marginals`で解析した場合のみ)
+    pub prob: Option<f64>,
+
+    /// 単語ID. 形態素の由来(`kind`)ごとの辞書内でのID
+    pub word_id: i32,
+
+    /// 左文脈ID
+    pub left_id: i16,
+
+    /// 右文脈ID
+    pub right_id: i16,
+
+    /// 単語のコスト
+    pub word_cost: i32,
+
+    /// 文頭から形態素までの最小の累積コスト
+    pub cost: i32,
+
+    /// 形態素の由来
+    pub kind: NodeKind,
+
+    /// 素性の項目の並び
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub schema: FeatureSchema
 }
+
+impl MorphemeBuf {
+    /// 未知語処理により生成された形態素かどうか
+    pub fn is_unknown(&self) -> bool {
+        self.kind == NodeKind::Unknown
+    }
+}
+
+impl_feature_accessors!(Morpheme<'_, '_>);
+impl_feature_accessors!(MorphemeBuf);
@@ -0,0 +1,520 @@
+//! バイナリ辞書ファイルの読み書き
+//!
+//! 辞書ファイルは先頭にヘッダを持ち、続く配列は書き込み時に0で埋めて要素のサイズ境界に揃えて配置されている。
+//! ヘッダのサイズは配列の要素のサイズの倍数であるため、メモリマップしたファイルを複製せずにそのまま配列として参照できる。
+//! ヘッダの無いファイルは、オリジナルのJava版などで構築された旧形式(バージョン1)として扱う。
+//!
+//! ヘッダの書式(32バイト). マジックナンバー以外の値はファイルのバイト順で格納される
+//!
+//! | 位置 | サイズ | 内容 |
+//! |------|--------|------|
+//! | 0    | 8      | マジックナンバー `IGO-RS\0\0` |
+//! | 8    | 4      | バイト順の判定用の値 `0x01020304` |
+//! | 12   | 4      | 書式のバージョン |
+//! | 16   | 8      | ヘッダに続くデータのバイト数 |
+//! | 24   | 4      | データのCRC-32 |
+//! | 28   | 4      | 予約(0) |
+
+use std::fmt;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::ops::Deref;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::str;
+
+#[cfg(feature = "mmap")]
+use std::{marker::PhantomData, sync::Arc};
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+/// 辞書ファイルの先頭に置かれるマジックナンバー
+pub const MAGIC: &[u8; 8] = b"IGO-RS\0\0";
+/// 現在の辞書ファイルの書式のバージョン
+pub const FORMAT_VERSION: u32 = 3;
+/// ヘッダの無い旧形式の辞書ファイルの書式のバージョン
+pub const LEGACY_FORMAT_VERSION: u32 = 1;
+/// ヘッダのサイズ(バイト数)
+pub const HEADER_SIZE: usize = 32;
+
+// NOTE: ヘッダの直後の位置が、全ての配列の要素のサイズ境界に揃っている必要がある
+const _: () = assert!(padding_len(HEADER_SIZE, mem::align_of::<u64>()) == 0);
+
+/// バイナリ辞書を構成するファイル名のリスト
+pub const DIC_FILES: &[&str] = &[
+    "word2id",
+    "word.dat",
+    "word.ary.idx",
+    "word.inf",
+    "matrix.bin",
+    "code2category",
+    "char.category",
+];
+
+const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+
+/// 辞書ファイルのヘッダ
+struct Header {
+    version: u32,
+    big_endian: bool,
+    data_len: u64,
+    checksum: u32,
+}
+
+impl Header {
+    /// ヘッダを読み取る. ヘッダが無い場合は`None`を返す
+    fn parse(name: &str, bytes: &[u8]) -> io::Result<Option<Header>> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Ok(None);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid_data(name, "dictionary file header is truncated"));
+        }
+        let big_endian = match u32::from_bytes(&bytes[8..12], false) {
+            BYTE_ORDER_MARK => false,
+            m if m.swap_bytes() == BYTE_ORDER_MARK => true,
+            m => {
+                return Err(invalid_data(
+                    name,
+                    format!("invalid byte order mark: {:#010x}", m),
+                ))
+            }
+        };
+        let version = u32::from_bytes(&bytes[12..16], big_endian);
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(
+                name,
+                format!(
+                    "unsupported dictionary format version: {} (supported: {}); rebuild the dictionary",
+                    version, FORMAT_VERSION
+                ),
+            ));
+        }
+        Ok(Some(Header {
+            version,
+            big_endian,
+            data_len: u64::from_bytes(&bytes[16..24], big_endian),
+            checksum: u32::from_bytes(&bytes[24..28], big_endian),
+        }))
+    }
+
+    /// データの長さを検査する
+    fn check_len(&self, name: &str, data_len: usize) -> io::Result<()> {
+        if self.data_len != data_len as u64 {
+            return Err(invalid_data(
+                name,
+                format!(
+                    "dictionary file size mismatch: expected {} bytes of data, found {}",
+                    self.data_len, data_len
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// データのチェックサムを検査する
+    fn check_sum(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let checksum = crc32(data);
+        if self.checksum != checksum {
+            return Err(invalid_data(
+                name,
+                format!(
+                    "dictionary file checksum mismatch: expected {:#010x}, found {:#010x}",
+                    self.checksum, checksum
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data<S: Into<String>>(name: &str, msg: S) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", name, msg.into()),
+    )
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32(ISO-HDLC)を計算する
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| {
+        CRC32_TABLE[((c ^ u32::from(b)) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+/// 辞書ファイルの出力
+///
+/// 書き込まれた内容をメモリ上に溜めておき、`finish`でデータの長さとチェックサムを含むヘッダと共に出力する。
+/// 数値はリトルエンディアンで書き込む必要がある
+#[derive(Default)]
+pub struct DicWriter {
+    buf: Vec<u8>,
+}
+
+impl DicWriter {
+    pub fn new() -> DicWriter {
+        DicWriter::default()
+    }
+
+    /// 続けて書き込む配列が要素`T`のサイズ境界に揃うように、0で埋める
+    ///
+    /// 要素のサイズが異なる配列を書き込む前に呼び出す
+    pub fn align<T: Element>(&mut self) {
+        let padding = padding_len(self.buf.len(), mem::align_of::<T>());
+        self.buf.resize(self.buf.len() + padding, 0);
+    }
+
+    /// ヘッダと、書き込まれた内容を出力する
+    pub fn finish<W: Write>(self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&BYTE_ORDER_MARK.to_le_bytes())?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.buf.len() as u64).to_le_bytes())?;
+        writer.write_all(&crc32(&self.buf).to_le_bytes())?;
+        writer.write_all(&[0u8; 4])?;
+        writer.write_all(&self.buf)?;
+        writer.flush()
+    }
+
+    /// ヘッダと、書き込まれた内容を連結したデータを返す
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + self.buf.len());
+        self.finish(&mut buf).expect("writing to Vec never fails");
+        buf
+    }
+}
+
+impl Write for DicWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 位置`pos`を`align`の倍数に揃えるために必要なバイト数
+pub(crate) const fn padding_len(pos: usize, align: usize) -> usize {
+    (align - pos % align) % align
+}
+
+/// 辞書ファイルに格納される配列の要素
+///
+/// 任意のビット列が有効な値となる整数型のみが実装する
+pub trait Element: Copy + 'static {
+    fn from_bytes(bytes: &[u8], big_endian: bool) -> Self;
+}
+
+macro_rules! impl_element {
+    ($($t:ty),*) => {
+        $(
+            impl Element for $t {
+                fn from_bytes(bytes: &[u8], big_endian: bool) -> Self {
+                    let mut buf = [0u8; mem::size_of::<$t>()];
+                    buf.copy_from_slice(bytes);
+                    if big_endian {
+                        <$t>::from_be_bytes(buf)
+                    } else {
+                        <$t>::from_le_bytes(buf)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(u64, u32, i32, i16, u16, u8);
+
+/// 辞書ファイルから読み込んだ配列
+///
+/// 読み込み時に複製した配列か、メモリマップしたファイル上の領域を参照する
+pub enum Array<T: Element> {
+    Owned(Box<[T]>),
+    #[cfg(feature = "mmap")]
+    Mapped {
+        map: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+        marker: PhantomData<T>,
+    },
+}
+
+impl<T: Element> Deref for Array<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Array::Owned(v) => v,
+            #[cfg(feature = "mmap")]
+            Array::Mapped {
+                map, offset, len, ..
+            } => {
+                // SAFETY: 作成時に範囲とアラインメント、バイト順を検査済み. `T`は任意のビット列が有効な整数型であり、
+                // マップした領域は`Arc`で保持されている間は解放されない
+                unsafe { std::slice::from_raw_parts(map.as_ptr().add(*offset) as *const T, *len) }
+            }
+        }
+    }
+}
+
+impl<T: Element> Clone for Array<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Array::Owned(v) => Array::Owned(v.clone()),
+            #[cfg(feature = "mmap")]
+            Array::Mapped {
+                map, offset, len, ..
+            } => Array::Mapped {
+                map: Arc::clone(map),
+                offset: *offset,
+                len: *len,
+                marker: PhantomData,
+            },
+        }
+    }
+}
+
+impl<T: Element> fmt::Debug for Array<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Array::Owned(v) => write!(f, "Array::Owned(len: {})", v.len()),
+            #[cfg(feature = "mmap")]
+            Array::Mapped { len, .. } => write!(f, "Array::Mapped(len: {})", len),
+        }
+    }
+}
+
+impl<T: Element> From<Vec<T>> for Array<T> {
+    fn from(v: Vec<T>) -> Self {
+        Array::Owned(v.into_boxed_slice())
+    }
+}
+
+/// UTF-8であることを検査済みのバイト列
+#[derive(Clone, Debug)]
+pub struct Text(Array<u8>);
+
+impl Text {
+    pub fn new(bytes: Array<u8>) -> io::Result<Text> {
+        str::from_utf8(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Text(bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: 作成時にUTF-8として検査済みで、内容は変更されない
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+}
+
+impl From<String> for Text {
+    fn from(s: String) -> Self {
+        Text(Array::from(s.into_bytes()))
+    }
+}
+
+/// 開いた辞書ファイル
+///
+/// 先頭から順に配列を読み込む
+pub struct DicFile {
+    name: String,
+    version: u32,
+    big_endian: bool,
+    source: Source,
+    pos: usize,
+}
+
+enum Source {
+    Buffer(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<Mmap>),
+}
+
+impl DicFile {
+    /// ファイルの内容を読み込んで複製するモードで開く
+    /// # Arguments
+    /// * `name`   - エラーの報告に使用するファイル名
+    /// * `reader` - ファイルの内容
+    pub fn read(name: &str, mut reader: Box<dyn Read>) -> io::Result<DicFile> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Self::from_bytes(name, buf)
+    }
+
+    /// 読み込み済みのファイルの内容から作成する
+    ///
+    /// ヘッダのデータの長さとチェックサムを検査する
+    /// # Arguments
+    /// * `name` - エラーの報告に使用するファイル名
+    /// * `buf`  - ファイルの内容
+    pub fn from_bytes(name: &str, buf: Vec<u8>) -> io::Result<DicFile> {
+        let (version, big_endian, pos) = match Header::parse(name, &buf)? {
+            Some(header) => {
+                header.check_len(name, buf.len() - HEADER_SIZE)?;
+                header.check_sum(name, &buf[HEADER_SIZE..])?;
+                (header.version, header.big_endian, HEADER_SIZE)
+            }
+            // 旧形式のファイルはネイティブエンディアンで格納されている
+            None => (LEGACY_FORMAT_VERSION, cfg!(target_endian = "big"), 0),
+        };
+        Ok(DicFile {
+            name: name.to_string(),
+            version,
+            big_endian,
+            source: Source::Buffer(buf),
+            pos,
+        })
+    }
+
+    /// ファイルをメモリマップして開く
+    ///
+    /// 読み込みを速くするため、ヘッダのデータの長さは検査するが、チェックサムは検査しない。
+    /// 旧形式のファイルと、バイト順が実行環境と異なるファイルはメモリマップできないため、エラーとなる
+    #[cfg(feature = "mmap")]
+    pub fn map(path: &Path) -> io::Result<DicFile> {
+        let name = path.display().to_string();
+        let file = File::open(path)?;
+        // SAFETY: 辞書ファイルは読み込み専用として扱い、マップ中に変更されないことを前提とする
+        let map = unsafe { Mmap::map(&file)? };
+        let header = Header::parse(&name, &map)?.ok_or_else(|| {
+            invalid_data(
+                &name,
+                "legacy dictionary files cannot be memory-mapped; rebuild the dictionary",
+            )
+        })?;
+        header.check_len(&name, map.len() - HEADER_SIZE)?;
+        if header.big_endian != cfg!(target_endian = "big") {
+            return Err(invalid_data(
+                &name,
+                "byte order of the dictionary file differs from this machine; load it without mmap",
+            ));
+        }
+        Ok(DicFile {
+            name,
+            version: header.version,
+            big_endian: header.big_endian,
+            source: Source::Mapped(Arc::new(map)),
+            pos: HEADER_SIZE,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.source {
+            Source::Buffer(ref buf) => buf,
+            #[cfg(feature = "mmap")]
+            Source::Mapped(ref map) => map,
+        }
+    }
+
+    /// 書式のバージョン
+    pub(crate) fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// 未読のバイト数
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes().len() - self.pos
+    }
+
+    /// 配列の前にある、要素`T`のサイズ境界に揃えるための詰め物を読み飛ばす
+    ///
+    /// 旧形式のファイルには詰め物が無い
+    fn skip_padding<T: Element>(&mut self) -> io::Result<()> {
+        if self.version == LEGACY_FORMAT_VERSION {
+            return Ok(());
+        }
+        let padding = padding_len(self.pos, mem::align_of::<T>());
+        if padding > self.remaining() {
+            return Err(invalid_data(
+                &self.name,
+                format!("dictionary file is truncated at offset {}", self.pos),
+            ));
+        }
+        self.pos += padding;
+        Ok(())
+    }
+
+    /// 配列を読み込む
+    /// # Arguments
+    /// * `count` - 配列の要素数
+    pub(crate) fn read_array<T: Element>(&mut self, count: usize) -> io::Result<Array<T>> {
+        self.skip_padding::<T>()?;
+        let size = count
+            .checked_mul(mem::size_of::<T>())
+            .filter(|&size| size <= self.remaining())
+            .ok_or_else(|| {
+                invalid_data(
+                    &self.name,
+                    format!(
+                        "dictionary file is truncated: {} bytes required at offset {}, {} remaining",
+                        count.saturating_mul(mem::size_of::<T>()),
+                        self.pos,
+                        self.remaining()
+                    ),
+                )
+            })?;
+        let offset = self.pos;
+        self.pos += size;
+        match self.source {
+            Source::Buffer(ref buf) => Ok(Array::from(
+                buf[offset..(offset + size)]
+                    .chunks_exact(mem::size_of::<T>())
+                    .map(|b| T::from_bytes(b, self.big_endian))
+                    .collect::<Vec<_>>(),
+            )),
+            #[cfg(feature = "mmap")]
+            Source::Mapped(ref map) => {
+                // NOTE: マップした領域の先頭はページ境界にあり、詰め物により配列の位置は要素のサイズの倍数になっている
+                if map[offset..].as_ptr().align_offset(mem::align_of::<T>()) != 0 {
+                    return Err(invalid_data(
+                        &self.name,
+                        format!("misaligned array at offset {}", offset),
+                    ));
+                }
+                Ok(Array::Mapped {
+                    map: Arc::clone(map),
+                    offset,
+                    len: count,
+                    marker: PhantomData,
+                })
+            }
+        }
+    }
+
+    /// 残り全てを配列として読み込む
+    pub(crate) fn read_to_end<T: Element>(&mut self) -> io::Result<Array<T>> {
+        self.skip_padding::<T>()?;
+        self.read_array(self.remaining() / mem::size_of::<T>())
+    }
+
+    /// 値を一つ読み込む
+    pub(crate) fn read_value<T: Element>(&mut self) -> io::Result<T> {
+        Ok(self.read_array::<T>(1)?[0])
+    }
+}
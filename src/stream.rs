@@ -0,0 +1,170 @@
+//! 入力を少しずつ読み込みながら形態素解析を行う
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::str;
+
+use crate::morpheme::MorphemeBuf;
+use crate::tagger::{ParseContext, Tagger};
+
+/// 文の終わりとみなす文字
+const SENTENCE_TERMINATORS: &[char] = &['。', '．', '！', '？', '!', '?', '\n'];
+
+/// 一度に解析するテキストの最大長の既定値(バイト単位)
+pub const DEFAULT_MAX_CHUNK_LEN: usize = 64 * 1024;
+
+/// `io::BufRead`から読み込んだテキストを、文ごとに区切って形態素解析するイテレータ
+///
+/// テキストは文の終わりの文字(`。`や改行など)の直後で区切って解析するため、
+/// 入力全体の長さに関わらず使用するメモリは一定に保たれる。
+/// 文の終わりが現れないまま最大長に達した場合は、最大長の位置で区切る。
+///
+/// 得られる形態素の位置(`start`, `byte_range`, `char_range`)は入力全体の先頭からの位置になる
+pub struct TokenStream<'a, R: BufRead> {
+    tagger: &'a Tagger,
+    reader: R,
+    max_chunk_len: usize,
+    /// 読み込み済みで、まだ解析していない入力
+    buf: Vec<u8>,
+    eof: bool,
+    failed: bool,
+    ctx: ParseContext,
+    tokens: VecDeque<MorphemeBuf>,
+    /// 解析済みの入力の長さ(バイト単位, UTF-16単位, 文字単位)
+    consumed: (usize, usize, usize),
+}
+
+impl<'a, R: BufRead> TokenStream<'a, R> {
+    /// # Arguments
+    /// * `tagger` - 形態素解析器
+    /// * `reader` - UTF-8のテキストの読み込み元
+    pub fn new(tagger: &'a Tagger, reader: R) -> TokenStream<'a, R> {
+        TokenStream {
+            tagger,
+            reader,
+            max_chunk_len: DEFAULT_MAX_CHUNK_LEN,
+            buf: Vec::new(),
+            eof: false,
+            failed: false,
+            ctx: ParseContext::new(),
+            tokens: VecDeque::new(),
+            consumed: (0, 0, 0),
+        }
+    }
+
+    /// 一度に解析するテキストの最大長を設定する
+    ///
+    /// 最大長の位置で区切った場合、区切りをまたぐ形態素は得られない
+    /// # Arguments
+    /// * `len` - テキストの最大長(バイト単位). 1文字のバイト数(4)以上でなければならない
+    pub fn set_max_chunk_len(&mut self, len: usize) {
+        assert!(len >= 4, "max chunk length must be at least 4 bytes");
+        self.max_chunk_len = len;
+    }
+
+    /// 次に解析するテキストの長さ(バイト単位)を求める. 入力が足りない場合は`None`を返す
+    fn chunk_len(&self) -> io::Result<Option<usize>> {
+        let valid = match str::from_utf8(&self.buf) {
+            Ok(s) => s,
+            Err(e) => {
+                if e.error_len().is_some() || self.eof {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                // 末尾の文字が途中までしか読み込まれていない
+                str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap()
+            }
+        };
+
+        if let Some((i, c)) = valid
+            .char_indices()
+            .find(|&(i, c)| i < self.max_chunk_len && SENTENCE_TERMINATORS.contains(&c))
+        {
+            return Ok(Some(i + c.len_utf8()));
+        }
+        if self.buf.len() >= self.max_chunk_len {
+            let mut end = self.max_chunk_len.min(valid.len());
+            while !valid.is_char_boundary(end) {
+                end -= 1;
+            }
+            return Ok(Some(end));
+        }
+        if self.eof && !valid.is_empty() {
+            return Ok(Some(valid.len()));
+        }
+        Ok(None)
+    }
+
+    /// 改行までか、最大長に達するまで入力を読み込む
+    fn fill_buf(&mut self) -> io::Result<()> {
+        while self.buf.len() < self.max_chunk_len {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                self.eof = true;
+                return Ok(());
+            }
+            let limit = available.len().min(self.max_chunk_len - self.buf.len());
+            let (len, newline) = match available[..limit].iter().position(|&b| b == b'\n') {
+                Some(p) => (p + 1, true),
+                None => (limit, false),
+            };
+            self.buf.extend_from_slice(&available[..len]);
+            self.reader.consume(len);
+            if newline {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// テキストを一つ解析して、得られた形態素を`tokens`に追加する
+    fn parse_chunk(&mut self, len: usize) {
+        let text = str::from_utf8(&self.buf[..len]).unwrap();
+        let mut morphemes = Vec::new();
+        self.tagger.parse_into(&mut self.ctx, text, &mut morphemes);
+
+        let (bytes, utf16, chars) = self.consumed;
+        self.tokens.extend(morphemes.iter().map(|m| {
+            let mut m = m.to_owned();
+            m.start += utf16;
+            m.byte_range = (m.byte_range.start + bytes)..(m.byte_range.end + bytes);
+            m.char_range = (m.char_range.start + chars)..(m.char_range.end + chars);
+            m
+        }));
+        self.consumed.0 += len;
+        for c in text.chars() {
+            self.consumed.1 += c.len_utf16();
+            self.consumed.2 += 1;
+        }
+        self.buf.drain(..len);
+    }
+}
+
+impl<'a, R: BufRead> Iterator for TokenStream<'a, R> {
+    type Item = io::Result<MorphemeBuf>;
+
+    fn next(&mut self) -> Option<io::Result<MorphemeBuf>> {
+        while self.tokens.is_empty() {
+            if self.failed {
+                return None;
+            }
+            let len = match self.chunk_len() {
+                Ok(len) => len,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            };
+            match len {
+                Some(len) => self.parse_chunk(len),
+                None if self.eof => return None,
+                None => {
+                    if let Err(e) = self.fill_buf() {
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+        self.tokens.pop_front().map(Ok)
+    }
+}
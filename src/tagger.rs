@@ -1,30 +1,30 @@
-use std::io;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, BufRead};
 use std::path::Path;
-use std::rc::Rc;
 
 use log::debug;
 
-use crate::dictionary::{self, Matrix, Unknown, ViterbiNode, WordDic};
+use crate::bundle::Bundle;
+use crate::constraint::{Constraints, ResolvedConstraints};
+use crate::dictionary::build::AppResult;
+use crate::dictionary::{self, Callback, Matrix, Unknown, UserDic, ViterbiNode, WordDic};
+use crate::feature::FeatureSchema;
+use crate::lattice::{Lattice, LatticeNode, NodeKind};
 use crate::morpheme::Morpheme;
+use crate::stream::TokenStream;
 use crate::util::DirLike;
-use crate::{Utf16Str, Utf16String};
-
-type ViterbiNodeList = Vec<Rc<ViterbiNode>>;
-
-fn bos_nodes() -> ViterbiNodeList {
-    vec![Rc::new(ViterbiNode::make_boseos())]
-}
-
-fn empty_vec() -> ViterbiNodeList {
-    Vec::new()
-}
 
 /// 形態素解析を行う
+///
+/// 解析中に内部の状態を変更しないため、`Arc`で包んで複数のスレッドから共有できる
 #[derive(Clone)]
 pub struct Tagger {
     wdc: WordDic,
     unk: Unknown,
     mtx: Matrix,
+    udic: Option<UserDic>,
+    schema: FeatureSchema,
 }
 
 impl Tagger {
@@ -37,9 +37,22 @@ impl Tagger {
             wdc: WordDic::new(&mut dir_like)?,
             unk: Unknown::new(&mut dir_like)?,
             mtx: Matrix::new(&mut dir_like)?,
+            udic: None,
+            schema: FeatureSchema::default(),
         })
     }
 
+    /// バイナリ辞書をメモリマップして、形態素解析器のインスタンスを作成する
+    ///
+    /// 辞書の内容は複製されないため、読み込みが速く、同じ辞書を使う複数のプロセスでメモリが共有される。
+    /// ヘッダを持つ書式で構築した辞書のみ読み込める
+    /// # Arguments
+    /// * `data_dir` - バイナリ辞書があるディレクトリ
+    #[cfg(feature = "mmap")]
+    pub fn new_mmap(data_dir: &Path) -> io::Result<Tagger> {
+        Self::load_from_dir(&mut crate::util::MmapDir(data_dir))
+    }
+
     /// zip等にアーカイブしたバイナリ辞書を読み込んで、形態素解析器のインスタンスを作成する
     ///
     /// WebAssembly等、ファイルシステムに直接アクセスできない環境向け
@@ -50,154 +63,709 @@ impl Tagger {
             wdc: WordDic::new(dir)?,
             unk: Unknown::new(dir)?,
             mtx: Matrix::new(dir)?,
+            udic: None,
+            schema: FeatureSchema::default(),
         })
     }
 
+    /// 単一ファイルにまとめたバイナリ辞書(バンドル)の内容から、形態素解析器のインスタンスを作成する
+    ///
+    /// `include_bytes!`で埋め込んだ辞書や、WebAssembly等でファイルシステムを使わずに取得した辞書向け
+    /// # Arguments
+    /// * `bytes` - バンドルの内容
+    pub fn from_bundle(bytes: &[u8]) -> io::Result<Tagger> {
+        Self::load_from_dir(&mut Bundle::new(bytes)?)
+    }
+
+    /// 単一ファイルにまとめたバイナリ辞書(バンドル)を読み込んで、形態素解析器のインスタンスを作成する
+    /// # Arguments
+    /// * `path` - バンドルのファイルのパス
+    pub fn from_bundle_file(path: &Path) -> io::Result<Tagger> {
+        Self::load_from_dir(&mut Bundle::from_file(path)?)
+    }
+
+    /// ユーザー辞書を設定する
+    ///
+    /// ユーザー辞書の単語は、システム辞書の単語と共に検索される
+    /// # Arguments
+    /// * `user_dic` - ユーザー辞書. `None`の場合は設定済みのユーザー辞書を外す
+    pub fn set_user_dic(&mut self, user_dic: Option<UserDic>) -> AppResult<()> {
+        if let Some(ref udic) = user_dic {
+            udic.validate(&self.mtx)?;
+        }
+        self.udic = user_dic;
+        Ok(())
+    }
+
+    /// 素性の項目の並びを設定する
+    ///
+    /// `Morpheme::pos`等のアクセサは、この並びに従って素性から項目を取り出す. 既定はIPADICの並び
+    /// # Arguments
+    /// * `schema` - 素性の項目の並び
+    pub fn set_feature_schema(&mut self, schema: FeatureSchema) {
+        self.schema = schema;
+    }
+
+    /// 設定されている素性の項目の並び
+    pub fn feature_schema(&self) -> &FeatureSchema {
+        &self.schema
+    }
+
     /// 形態素解析を行う
     /// # Arguments
     /// * `text` - 解析対象テキスト
     pub fn parse<'a, 'b>(&'a self, text: &'b str) -> Vec<Morpheme<'a, 'b>> {
-        let utf16_text: Utf16String = text.encode_utf16().collect::<Vec<_>>();
-        let utf8_offsets = utf8_char_offsets(text, utf16_text.len());
+        let mut results = Vec::new();
+        self.parse_into(&mut ParseContext::new(), text, &mut results);
+        results
+    }
+
+    /// 作業領域を再利用して形態素解析を行う
+    ///
+    /// 作業領域と`out`の容量が足りていれば、ヒープ領域を確保せずに解析できる
+    /// # Arguments
+    /// * `ctx`  - 解析の作業領域
+    /// * `text` - 解析対象テキスト
+    /// * `out`  - 解析結果の格納先. 元の内容は消去される
+    pub fn parse_into<'a, 'b>(
+        &'a self,
+        ctx: &mut ParseContext,
+        text: &'b str,
+        out: &mut Vec<Morpheme<'a, 'b>>,
+    ) {
+        self.build_unconstrained(text, &mut ctx.lattice);
+        let (mut cur, len) = ctx.lattice.link_best_path();
+
+        out.clear();
+        out.reserve(len);
+        let mut cursor = TextCursor::new(text);
+        while let Some(idx) = cur {
+            out.push(self.to_morpheme(&mut cursor, ctx.lattice.node(idx)));
+            cur = ctx.lattice.next_on_best_path(idx);
+        }
+    }
 
-        self.parse_impl(&utf16_text)
+    /// 複数のテキストの形態素解析を行う
+    ///
+    /// `rayon`フィーチャーを有効にすると、テキストごとに並列に解析する
+    /// # Arguments
+    /// * `texts` - 解析対象テキストのリスト
+    pub fn parse_batch<'a, 'b>(&'a self, texts: &[&'b str]) -> Vec<Vec<Morpheme<'a, 'b>>> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            texts.par_iter().map(|text| self.parse(text)).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            texts.iter().map(|text| self.parse(text)).collect()
+        }
+    }
+
+    /// `io::BufRead`から読み込んだテキストを、文ごとに区切って形態素解析する
+    ///
+    /// 入力全体を読み込まずに解析するため、大きなテキストでも使用するメモリは一定に保たれる
+    /// # Arguments
+    /// * `reader` - UTF-8のテキストの読み込み元
+    pub fn token_stream<R: BufRead>(&self, reader: R) -> TokenStream<'_, R> {
+        TokenStream::new(self, reader)
+    }
+
+    /// 形態素解析を行い、コストの小さい順に上位N件の解析結果を返す
+    ///
+    /// MeCabの`-N`オプションに相当する
+    /// # Arguments
+    /// * `text` - 解析対象テキスト
+    /// * `n`    - 取得する解析結果の最大数
+    /// # Return
+    /// 形態素のリストと、その経路の総コストの組のリスト(コストの昇順)
+    pub fn parse_nbest<'a, 'b>(
+        &'a self,
+        text: &'b str,
+        n: usize,
+    ) -> Vec<(Vec<Morpheme<'a, 'b>>, i32)> {
+        let lattice = self.make_lattice(text);
+        self.nbest_impl(&lattice, n)
             .into_iter()
-            .map(|n| {
-                let from = utf8_offsets[n.start];
-                let to = utf8_offsets[n.start + (n.length as usize)];
-
-                Morpheme {
-                    surface: &text[from..to],
-                    feature: self.wdc.word_data(n.word_id),
-                    start: n.start,
-                }
-            })
+            .map(|(path, cost)| (self.to_morphemes(text, &lattice, &path), cost))
             .collect()
     }
 
+    /// 境界や素性の制約を満たすように形態素解析を行う
+    ///
+    /// MeCabの部分解析に相当する。
+    /// 範囲を指定した形態素の素性のパターンに一致する候補が無い場合は、パターンを無視して未知語として解析する
+    /// # Arguments
+    /// * `text`        - 解析対象テキスト
+    /// * `constraints` - 解析時の制約
+    /// # Errors
+    /// 制約の位置が文字の境界に無い場合、範囲の制約が空か重複している場合、及び制約を満たす経路が無い場合
+    pub fn parse_with_constraints<'a, 'b>(
+        &'a self,
+        text: &'b str,
+        constraints: &Constraints,
+    ) -> AppResult<Vec<Morpheme<'a, 'b>>> {
+        let constraints = constraints.resolve(text)?;
+        let mut lattice = Lattice::new(text.len());
+        self.build_lattice(text, Some(&constraints), &mut lattice)?;
+        Ok(self.to_morphemes(text, &lattice, &lattice.best_path()))
+    }
+
+    /// 形態素解析を行い、各形態素に周辺確率を付与する
+    ///
+    /// 周辺確率はラティス上の全経路に対する前向き・後向きアルゴリズムで求める(MeCabの`-m`オプションに相当する)。
+    /// 経路の確率は`exp(-theta * 経路の総コスト)`に比例する
+    /// # Arguments
+    /// * `text`  - 解析対象テキスト
+    /// * `theta` - コストに掛ける係数. 小さいほど確率分布がなだらかになる
+    pub fn parse_with_marginals<'a, 'b>(
+        &'a self,
+        text: &'b str,
+        theta: f64,
+    ) -> Vec<Morpheme<'a, 'b>> {
+        let lattice = self.make_lattice(text);
+        let marginals = self.marginals(&lattice, theta);
+        let path = lattice.best_path();
+        let mut morphemes = self.to_morphemes(text, &lattice, &path);
+        for (m, &idx) in morphemes.iter_mut().zip(path.iter()) {
+            m.prob = Some(marginals[idx]);
+        }
+        morphemes
+    }
+
+    /// 形態素解析を行い、形態素を順に返すイテレータを返す
+    ///
+    /// 形態素は取り出す度に作成されるため、多くの形態素を読み捨てる場合に`parse`より効率が良い。
+    /// 経路はラティス上のノードのリンクを辿るため、ラティス以外の領域は確保しない
+    /// # Arguments
+    /// * `text` - 解析対象テキスト
+    pub fn tokens<'a, 'b>(&'a self, text: &'b str) -> Tokens<'a, 'b> {
+        let mut lattice = self.make_lattice(text);
+        let (head, len) = lattice.link_best_path();
+        Tokens {
+            tagger: self,
+            lattice,
+            head,
+            len,
+            cursor: TextCursor::new(text),
+        }
+    }
+
     /// 分かち書きを行う
     /// # Arguments
     /// * `text` - 分かち書きされるテキスト
     pub fn wakati(&self, text: &str) -> Vec<String> {
-        let utf16_text: Utf16String = text.encode_utf16().collect::<Vec<_>>();
-        self.parse_impl(&utf16_text)
-            .into_iter()
-            .map(|n| String::from_utf16_lossy(&utf16_text[n.start..n.start + (n.length as usize)]))
+        self.wakati_iter(text).map(|s| s.to_string()).collect()
+    }
+
+    /// 分かち書きを行い、各形態素の表層形を入力テキストの部分文字列として順に返すイテレータを返す
+    /// # Arguments
+    /// * `text` - 分かち書きされるテキスト
+    pub fn wakati_iter<'b>(&self, text: &'b str) -> Surfaces<'b> {
+        let mut lattice = self.make_lattice(text);
+        let (head, len) = lattice.link_best_path();
+        let tail = lattice.node(lattice.eos()).prev;
+        Surfaces {
+            text,
+            lattice,
+            head,
+            tail,
+            len,
+        }
+    }
+
+    /// 形態素ラティスを作成する
+    ///
+    /// 単語辞書及び未知語処理で得られた全ての候補ノードと、Viterbiアルゴリズムで求めた各ノードのコストを保持する
+    /// # Arguments
+    /// * `text` - 解析対象テキスト
+    pub fn lattice(&self, text: &str) -> Lattice {
+        self.make_lattice(text)
+    }
+
+    /// ラティス上のノードの素性を返す
+    ///
+    /// BOS/EOSノードの場合は`"BOS/EOS"`を返す. ユーザー辞書のノードで、ラティスの作成後にユーザー辞書を外した、
+    /// または差し替えた場合は空文字列を返す
+    pub fn feature(&self, node: &LatticeNode) -> &str {
+        match self.udic {
+            Some(ref udic) if node.kind == NodeKind::User && node.user_dic_id() != udic.id() => "",
+            _ => self.word_data(node.kind, node.word_id),
+        }
+    }
+
+    /// 読み込んだシステム辞書の単語辞書を返す
+    ///
+    /// `WordDic::lookup`や`WordDic::common_prefix`で、形態素解析を行わずに単語を検索できる
+    pub fn word_dic(&self) -> &WordDic {
+        &self.wdc
+    }
+
+    /// 表層形と文脈IDが同じ単語の素性を全て返す
+    ///
+    /// システム辞書の単語、ユーザー辞書の単語の順に、それぞれ辞書に格納された順に並ぶ。
+    /// 解析結果の形態素(`surface`, `left_id`, `right_id`)を渡すと、読みや原形だけが異なる候補が得られる。
+    /// システム辞書でこのような単語が残るのは、`DictionaryBuilder::set_keep_homographs`を指定して構築した場合のみ
+    /// # Arguments
+    /// * `surface`  - 表層形
+    /// * `left_id`  - 左文脈ID
+    /// * `right_id` - 右文脈ID
+    pub fn homographs(&self, surface: &str, left_id: i16, right_id: i16) -> Vec<&str> {
+        let mut features = self.wdc.homographs(surface, left_id, right_id);
+        if let Some(ref udic) = self.udic {
+            features.extend(udic.homographs(surface, left_id, right_id));
+        }
+        features
+    }
+
+    /// 右文脈IDが`right_id`の形態素と、左文脈IDが`left_id`の形態素の連接コスト
+    pub(crate) fn link_cost(&self, right_id: i16, left_id: i16) -> i32 {
+        self.mtx.link_cost(right_id, left_id)
+    }
+
+    fn word_data(&self, kind: NodeKind, word_id: i32) -> &str {
+        match kind {
+            NodeKind::Bos | NodeKind::Eos => "BOS/EOS",
+            NodeKind::Known | NodeKind::Unknown => self.wdc.word_data(word_id),
+            // ユーザー辞書を外した後は、以前のラティスのノードの素性は得られない
+            NodeKind::User => match self.udic {
+                Some(ref udic) => udic.word_data(word_id),
+                None => "",
+            },
+        }
+    }
+
+    /// ラティス上の各ノードの周辺確率を求める
+    /// # Arguments
+    /// * `lattice` - `Tagger::lattice`で作成したラティス
+    /// * `theta`   - コストに掛ける係数
+    /// # Return
+    /// ノードのインデックスを添字とする周辺確率のリスト
+    pub fn marginals(&self, lattice: &Lattice, theta: f64) -> Vec<f64> {
+        let nodes = lattice.nodes();
+        let link_weight = |prev: &LatticeNode, node: &LatticeNode| -> f64 {
+            -theta * f64::from(self.mtx.link_cost(prev.right_id, node.left_id) + node.word_cost)
+        };
+
+        // 前向き: BOSから各ノードまでの全経路の重みの対数和
+        // NOTE: ノードは開始位置の昇順に追加されているので、前方のノードは常に先に計算済みになる
+        let mut alpha = vec![f64::NEG_INFINITY; nodes.len()];
+        alpha[lattice.bos()] = 0.0;
+        for (idx, node) in nodes.iter().enumerate().skip(1) {
+            for &p in lattice.end_nodes(node.start) {
+                alpha[idx] = log_sum_exp(alpha[idx], alpha[p] + link_weight(&nodes[p], node));
+            }
+        }
+
+        // 後向き: 各ノードからEOSまでの全経路の重みの対数和
+        let mut beta = vec![f64::NEG_INFINITY; nodes.len()];
+        beta[lattice.eos()] = 0.0;
+        for (idx, node) in nodes.iter().enumerate().skip(1).rev() {
+            for &p in lattice.end_nodes(node.start) {
+                beta[p] = log_sum_exp(beta[p], beta[idx] + link_weight(&nodes[p], node));
+            }
+        }
+
+        let z = alpha[lattice.eos()];
+        alpha
+            .iter()
+            .zip(beta.iter())
+            .map(|(a, b)| (a + b - z).exp())
+            .collect()
+    }
+
+    fn to_morphemes<'a, 'b>(
+        &'a self,
+        text: &'b str,
+        lattice: &Lattice,
+        path: &[usize],
+    ) -> Vec<Morpheme<'a, 'b>> {
+        let mut cursor = TextCursor::new(text);
+        path.iter()
+            .map(|&idx| self.to_morpheme(&mut cursor, lattice.node(idx)))
             .collect()
     }
 
-    fn parse_impl(&self, utf16_text: &Utf16Str) -> Vec<Rc<ViterbiNode>> {
-        let len = utf16_text.len();
-        debug!("utf16_text.len: {}", len);
-        let mut nodes_ary: Vec<ViterbiNodeList> = Vec::with_capacity(len + 1);
-        nodes_ary.push(bos_nodes());
-        for _ in 1..=len {
-            nodes_ary.push(empty_vec());
+    /// 経路上のノードを形態素に変換する. ノードは経路の先頭から順に渡す必要がある
+    fn to_morpheme<'a, 'b>(
+        &'a self,
+        cursor: &mut TextCursor<'b>,
+        n: &LatticeNode,
+    ) -> Morpheme<'a, 'b> {
+        let (utf16_start, char_from) = cursor.advance(n.start);
+        let (_, char_to) = cursor.advance(n.end());
+
+        Morpheme {
+            surface: &cursor.text[n.start..n.end()],
+            feature: self.feature(n),
+            start: utf16_start,
+            byte_range: n.start..n.end(),
+            char_range: char_from..char_to,
+            prob: None,
+            word_id: n.word_id,
+            left_id: n.left_id,
+            right_id: n.right_id,
+            word_cost: n.word_cost,
+            cost: n.cost,
+            kind: n.kind,
+            schema: &self.schema,
         }
+    }
+
+    fn make_lattice(&self, text: &str) -> Lattice {
+        let mut lattice = Lattice::new(text.len());
+        self.build_unconstrained(text, &mut lattice);
+        lattice
+    }
+
+    /// 制約無しでラティスを構築する
+    ///
+    /// 未知語処理により全ての文字から形態素が生成されるため、EOSまでの経路は必ず存在する
+    fn build_unconstrained(&self, text: &str, lattice: &mut Lattice) {
+        self.build_lattice(text, None, lattice)
+            .expect("unconstrained lattice must reach EOS");
+    }
+
+    /// 確保済みのラティスを再利用して、ラティスを構築する
+    ///
+    /// 制約によって全ての経路が枝刈りされ、EOSに到達できない場合はエラーを返す
+    fn build_lattice(
+        &self,
+        text: &str,
+        constraints: Option<&ResolvedConstraints>,
+        lattice: &mut Lattice,
+    ) -> AppResult<()> {
+        let len = text.len();
+        debug!("text.len: {}", len);
 
-        let mut f = MakeLattice::new(self, nodes_ary.into_boxed_slice());
-        for i in 0..len {
-            if !f.nodes_ary[i].is_empty() {
+        lattice.reset(len, self.udic.as_ref().map_or(0, UserDic::id));
+        let mut f = MakeLattice::new(self, lattice, constraints);
+        for (i, _) in text.char_indices() {
+            if !f.lattice.end_nodes(i).is_empty() {
                 f.set(i);
-                self.wdc.search(utf16_text, i, &mut f); // 単語辞書から形態素を検索
-                self.unk.search(utf16_text, i, &self.wdc, &mut f); // 未知語辞書から形態素を検索
+                self.wdc.search(text, i, &mut f); // 単語辞書から形態素を検索
+                if let Some(ref udic) = self.udic {
+                    udic.search(text, i, &mut f); // ユーザー辞書から形態素を検索
+                }
+                self.unk.search(text, i, &self.wdc, &mut f); // 未知語辞書から形態素を検索
+
+                if let (true, Some(c)) = (f.is_empty(), constraints) {
+                    // 制約を満たす形態素が無い場合は、次の境界までを一つの未知語とする
+                    f.relax_feature = true;
+                    let length = c.next_boundary(i) - i;
+                    self.unk
+                        .search_with_length(text, i, length, &self.wdc, &mut f);
+                    f.relax_feature = false;
+                }
             }
         }
-        let nodes_ary: Box<[ViterbiNodeList]> = f.into_inner();
 
-        let mut cur: Rc<ViterbiNode> = self
-            .set_mincost_node(ViterbiNode::make_boseos(), &nodes_ary[len])
-            .prev
-            .unwrap();
+        let (prev, prev_cost) = self
+            .min_cost_prev(lattice, len, ViterbiNode::make_boseos().left_id)
+            .ok_or("no path satisfies the constraints")?;
+        lattice.add_eos(prev, prev_cost);
+        Ok(())
+    }
+
+    /// 前向きのViterbiで求めた各ノードのコストをヒューリスティックとして、
+    /// EOSから後ろ向きにA*探索を行い、コストの小さい順に経路を列挙する
+    fn nbest_impl(&self, lattice: &Lattice, n: usize) -> Vec<(Vec<usize>, i32)> {
+        let mut hyps: Vec<Hypothesis> = Vec::new();
+        let mut queue: BinaryHeap<Reverse<(i32, usize)>> = BinaryHeap::new();
+        hyps.push(Hypothesis {
+            node: lattice.eos(),
+            next: None,
+            back_cost: 0,
+        });
+        queue.push(Reverse((lattice.node(lattice.eos()).cost, 0)));
+
+        let mut results = Vec::with_capacity(n);
+        while results.len() < n {
+            let (total_cost, idx) = match queue.pop() {
+                Some(Reverse(e)) => e,
+                None => break,
+            };
+
+            let cur = lattice.node(hyps[idx].node);
+            if cur.kind == NodeKind::Bos {
+                // BOSに到達したので、経路が一つ確定した
+                let mut path = Vec::new();
+                let mut next = hyps[idx].next;
+                while let Some(i) = next {
+                    if hyps[i].next.is_some() {
+                        path.push(hyps[i].node);
+                    }
+                    next = hyps[i].next;
+                }
+                results.push((path, total_cost));
+                continue;
+            }
 
-        // reverse
-        let mut result: Vec<Rc<ViterbiNode>> = Vec::with_capacity(len / 2);
-        result.push(cur.clone());
-        while cur.prev.is_some() {
-            cur = cur.prev.as_ref().cloned().unwrap();
-            result.push(cur.clone());
+            let back_cost = hyps[idx].back_cost + cur.word_cost;
+            for &p in lattice.end_nodes(cur.start) {
+                let prev = lattice.node(p);
+                let back_cost = back_cost + self.mtx.link_cost(prev.right_id, cur.left_id);
+                queue.push(Reverse((prev.cost + back_cost, hyps.len())));
+                hyps.push(Hypothesis {
+                    node: p,
+                    next: Some(idx),
+                    back_cost,
+                });
+            }
         }
-        result.pop();
-        result.reverse();
 
-        result
+        results
     }
 
-    fn set_mincost_node(&self, mut vn: ViterbiNode, prevs: &ViterbiNodeList) -> ViterbiNode {
-        let mut min_idx = 0;
-        let p = &prevs[0];
-        let mut min_cost: i32 = p.cost + self.mtx.link_cost(p.right_id, vn.left_id);
+    /// 位置`pos`から始まるノードに対して、コスト最小となる前方のノードを求める
+    /// # Return
+    /// 前方のノードのインデックスと、始点からそのノードを経由して連接するまでのコストの組.
+    /// `pos`に連接可能なノードが無い場合は`None`
+    fn min_cost_prev(&self, lattice: &Lattice, pos: usize, left_id: i16) -> Option<(usize, i32)> {
+        let prevs = lattice.end_nodes(pos);
+        let mut min_idx = *prevs.first()?;
+        let p = lattice.node(min_idx);
+        let mut min_cost: i32 = p.cost + self.mtx.link_cost(p.right_id, left_id);
 
-        for i in 1..prevs.len() {
-            let p = &prevs[i];
-            let cost = p.cost + self.mtx.link_cost(p.right_id, vn.left_id);
+        for &idx in prevs.iter().skip(1) {
+            let p = lattice.node(idx);
+            let cost = p.cost + self.mtx.link_cost(p.right_id, left_id);
             if cost < min_cost {
                 min_cost = cost;
-                min_idx = i;
+                min_idx = idx;
             }
         }
 
-        vn.cost += min_cost;
-        vn.prev = Some(prevs[min_idx].clone());
+        Some((min_idx, min_cost))
+    }
+}
+
+fn log_sum_exp(x: f64, y: f64) -> f64 {
+    if x == f64::NEG_INFINITY {
+        y
+    } else if y == f64::NEG_INFINITY {
+        x
+    } else if x > y {
+        x + (y - x).exp().ln_1p()
+    } else {
+        y + (x - y).exp().ln_1p()
+    }
+}
+
+/// N-best探索時の仮説(EOSから途中のノードまでの部分経路)
+struct Hypothesis {
+    node: usize,
+    /// 部分経路上でEOS側に隣接する仮説のインデックス
+    next: Option<usize>,
+    /// nodeの右端からEOSまでのコスト
+    back_cost: i32,
+}
+
+/// テキストを先頭から走査して、バイト単位の位置をUTF-16単位と文字(`char`)単位の位置に変換する
+struct TextCursor<'a> {
+    text: &'a str,
+    byte: usize,
+    utf16: usize,
+    chars: usize,
+}
+
+impl<'a> TextCursor<'a> {
+    fn new(text: &'a str) -> TextCursor<'a> {
+        TextCursor {
+            text,
+            byte: 0,
+            utf16: 0,
+            chars: 0,
+        }
+    }
+
+    /// 位置`pos`(バイト単位)まで進めて、UTF-16単位と文字単位の位置の組を返す
+    ///
+    /// `pos`は前回の位置より前にあってはならない
+    fn advance(&mut self, pos: usize) -> (usize, usize) {
+        for c in self.text[self.byte..pos].chars() {
+            self.utf16 += c.len_utf16();
+            self.chars += 1;
+        }
+        self.byte = pos;
+        (self.utf16, self.chars)
+    }
+}
+
+/// 形態素解析の作業領域
+///
+/// `Tagger::parse_into`に繰り返し渡すことで、解析の度に確保していた領域を再利用する。
+/// 一つの作業領域を複数のスレッドで同時に使うことはできないため、スレッドごとに作成する
+#[derive(Debug, Clone)]
+pub struct ParseContext {
+    lattice: Lattice,
+}
+
+impl ParseContext {
+    pub fn new() -> ParseContext {
+        ParseContext {
+            lattice: Lattice::new(0),
+        }
+    }
+
+    /// 直前の`Tagger::parse_into`で構築したラティス
+    pub fn lattice(&self) -> &Lattice {
+        &self.lattice
+    }
+}
+
+impl Default for ParseContext {
+    fn default() -> Self {
+        ParseContext::new()
+    }
+}
+
+/// `Tagger::tokens`が返す、解析結果の形態素のイテレータ
+pub struct Tokens<'a, 'b> {
+    tagger: &'a Tagger,
+    lattice: Lattice,
+    /// 次に返すノードのインデックス
+    head: Option<usize>,
+    /// 残りのノードの数
+    len: usize,
+    cursor: TextCursor<'b>,
+}
+
+impl<'a, 'b> Iterator for Tokens<'a, 'b> {
+    type Item = Morpheme<'a, 'b>;
+
+    fn next(&mut self) -> Option<Morpheme<'a, 'b>> {
+        let idx = self.head?;
+        self.head = self.lattice.next_on_best_path(idx);
+        self.len -= 1;
+        Some(
+            self.tagger
+                .to_morpheme(&mut self.cursor, self.lattice.node(idx)),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Tokens<'_, '_> {}
+
+/// `Tagger::wakati_iter`が返す、形態素の表層形のイテレータ
+pub struct Surfaces<'b> {
+    text: &'b str,
+    lattice: Lattice,
+    /// 先頭から次に返すノードのインデックス
+    head: Option<usize>,
+    /// 末尾から次に返すノードのインデックス
+    tail: Option<usize>,
+    /// 残りのノードの数
+    len: usize,
+}
+
+impl<'b> Surfaces<'b> {
+    fn surface(&self, idx: usize) -> &'b str {
+        let n = self.lattice.node(idx);
+        &self.text[n.start..n.end()]
+    }
+}
+
+impl<'b> Iterator for Surfaces<'b> {
+    type Item = &'b str;
+
+    fn next(&mut self) -> Option<&'b str> {
+        if self.len == 0 {
+            return None;
+        }
+        let idx = self.head?;
+        self.head = self.lattice.next_on_best_path(idx);
+        self.len -= 1;
+        Some(self.surface(idx))
+    }
 
-        vn
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
     }
 }
 
-fn utf8_char_offsets(text: &str, num_chars: usize) -> Box<[usize]> {
-    let mut utf8_offsets: Vec<usize> = Vec::with_capacity(num_chars + 1);
-    let mut offset = 0usize;
-    for c in text.chars() {
-        utf8_offsets.push(offset);
-        if c.len_utf16() == 2 {
-            utf8_offsets.push(offset);
+impl DoubleEndedIterator for Surfaces<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
         }
-        offset += c.len_utf8();
+        let idx = self.tail?;
+        self.tail = self.lattice.node(idx).prev;
+        self.len -= 1;
+        Some(self.surface(idx))
     }
-    utf8_offsets.push(offset);
-    utf8_offsets.into_boxed_slice()
 }
 
+impl ExactSizeIterator for Surfaces<'_> {}
+
 struct MakeLattice<'a> {
     tagger: &'a Tagger,
-    nodes_ary: Box<[ViterbiNodeList]>,
+    lattice: &'a mut Lattice,
+    constraints: Option<&'a ResolvedConstraints>,
+    /// 素性のパターンの制約を無視するかどうか
+    relax_feature: bool,
     i: usize,
-    prevs: ViterbiNodeList,
     empty: bool,
 }
 
 impl<'a> MakeLattice<'a> {
-    fn new(tagger: &Tagger, nodes_ary: Box<[ViterbiNodeList]>) -> MakeLattice {
+    fn new(
+        tagger: &'a Tagger,
+        lattice: &'a mut Lattice,
+        constraints: Option<&'a ResolvedConstraints>,
+    ) -> MakeLattice<'a> {
         MakeLattice {
             tagger,
-            nodes_ary,
+            lattice,
+            constraints,
+            relax_feature: false,
             i: 0,
-            prevs: empty_vec(),
             empty: true,
         }
     }
 
     fn set(&mut self, i: usize) {
         self.i = i;
-        self.prevs = self.nodes_ary[i].clone();
-        self.nodes_ary[i] = empty_vec();
         self.empty = true;
     }
 
-    fn into_inner(self) -> Box<[ViterbiNodeList]> {
-        self.nodes_ary
+    /// 制約を満たさないノードを枝刈りする
+    fn accepts(&self, vn: &ViterbiNode, end: usize) -> bool {
+        match self.constraints {
+            Some(c) => {
+                let feature = if self.relax_feature {
+                    None
+                } else {
+                    Some(self.tagger.word_data(vn.kind, vn.word_id))
+                };
+                c.accepts(self.i, end, feature)
+            }
+            None => true,
+        }
     }
 }
 
 impl<'a> dictionary::Callback for MakeLattice<'a> {
     fn call(&mut self, vn: ViterbiNode) {
+        let end = self.i + vn.length;
+        if !self.accepts(&vn, end) {
+            return;
+        }
         self.empty = false;
-        let end = self.i + (vn.length as usize);
 
         if vn.is_space {
-            self.nodes_ary[end].extend(self.prevs.iter().cloned());
+            self.lattice.skip_space(self.i, end);
         } else {
-            self.nodes_ary[end].push(Rc::new(self.tagger.set_mincost_node(vn, &self.prevs)));
+            // NOTE: ノードは連接可能なノードがある位置からしか検索されない
+            if let Some((prev, prev_cost)) =
+                self.tagger.min_cost_prev(self.lattice, self.i, vn.left_id)
+            {
+                self.lattice.add(&vn, prev, prev_cost);
+            }
         }
     }
 
@@ -1,6 +1,5 @@
 use std::cmp;
 use bit_set::BitSet;
-use crate::Utf16Str;
 use crate::trie::node;
 
 
@@ -25,7 +24,7 @@ impl Allocator {
     /// * `codes`  - 遷移文字リスト。昇順にソートされている必要がある
     /// # Return
     /// 引数の遷移文字群を割り当て可能なベースノードのインデックス
-    pub fn x_check(&mut self, codes: &Utf16Str) -> i32 {
+    pub fn x_check(&mut self, codes: &[u8]) -> i32 {
         let mut cur = self.lnk[node::chck::CODE_LIMIT as usize].next;
         loop {
             let x = cur - codes[0] as usize;
@@ -42,7 +41,7 @@ impl Allocator {
         }
     }
 
-    fn can_allocate(&self, codes: &Utf16Str, x: usize) -> bool {
+    fn can_allocate(&self, codes: &[u8], x: usize) -> bool {
         for c in codes.iter().skip(1) {
             if (x + *c as usize) < self.lnk.len() && self.lnk[x + *c as usize].next == 0 {
                 return false;
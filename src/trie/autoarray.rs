@@ -7,6 +7,7 @@ pub trait AutoArray<E> {
     /// # Arguments
     /// * `index`         - リストの添字
     /// * `default_value` - リスト要素のデフォルト値
+    #[allow(dead_code)]
     fn get_auto(&mut self, index: usize, default_value: E) -> E;
 
     /// 範囲外アクセスがあった場合は、十分なサイズにまで自動的にリストが拡張される。
@@ -54,12 +55,12 @@ mod tests {
     fn test_auto_array() {
         let mut a: Vec<i32> = Vec::new();
         a.set_auto(0, 100, 200);
-        assert!(a.len() > 0);
+        assert!(!a.is_empty());
         assert_eq!(a[0], 100);
 
         let mut a: Vec<i32> = Vec::new();
         assert_eq!(a.get_auto(0, 100), 100);
-        assert!(a.len() > 0);
+        assert!(!a.is_empty());
         assert_eq!(a[0], 100);
 
         let mut a: Vec<i32> = Vec::new();
@@ -1,35 +1,28 @@
-use std::path::Path;
-use std::io::{BufWriter, Write};
-use std::fs::File;
-use byteorder::{WriteBytesExt, NativeEndian as NE};
-use crate::{Utf16Char, Utf16String};
-use crate::util::OutputUtil;
+use std::io::Write;
+use byteorder::{WriteBytesExt, LittleEndian as LE};
 use crate::dictionary::build::AppResult;
 use crate::trie::{AutoArray, Allocator, KeyStream};
 use crate::trie::node;
 use crate::trie::shrinktail;
+use crate::storage::DicWriter;
 use log::debug;
 
 
-/// キー文字列のリストから、DoubleArrayを構築し、ファイルに保存する
+/// キー文字列のリストから、DoubleArrayを構築し、writerに書き出す
+///
+/// キーはUTF-8のバイト単位で格納される。キーのIDは、キーをバイト列として昇順に並べた順位となる
 /// # Arguments
 /// * `key_list`  - DoubleArrayのキーとなる文字列のリスト. 破壊的に更新される
-/// * `filepath`  - DoubleArrayを保存するファイルのパス
-pub fn build(mut key_list: Vec<String>, filepath: &Path) -> AppResult<()> {
+/// * `writer`    - DoubleArrayの書き出し先
+pub fn build_to_writer<W: Write>(mut key_list: Vec<String>, writer: W) -> AppResult<()> {
     // ソート and ユニーク
     key_list.sort();
     key_list.dedup();
 
-    // UTF-16に変換する
-    let mut utf16_key_list = Vec::with_capacity(key_list.len());
-    for key in key_list {
-        utf16_key_list.push(key.encode_utf16().collect::<Vec<_>>())
-    }
-
-    let mut bld = Builder::new(&utf16_key_list);
+    let mut bld = Builder::new(&key_list);
     let end = bld.ks_list.len();
     bld.build_impl(&mut Allocator::new(), 0, end, 0);
-    bld.save(filepath)?;
+    bld.save(writer)?;
 
     Ok(())
 }
@@ -38,17 +31,17 @@ pub fn build(mut key_list: Vec<String>, filepath: &Path) -> AppResult<()> {
 struct Builder<'a> {
     ks_list: Vec<KeyStream<'a>>,
     base: Vec<i32>,
-    chck: Vec<Utf16Char>,
+    chck: Vec<u8>,
     begs: Vec<i32>,
-    lens: Vec<Utf16Char>,
-    tail: Utf16String
+    lens: Vec<u16>,
+    tail: Vec<u8>
 }
 
 impl<'a> Builder<'a> {
-    fn new(key_list: &'a [Utf16String]) -> Builder<'a> {
+    fn new(key_list: &'a [String]) -> Builder<'a> {
         let mut ks_list = Vec::with_capacity(key_list.len());
         for key in key_list {
-            ks_list.push(KeyStream::new(key, 0))
+            ks_list.push(KeyStream::new(key.as_bytes(), 0))
         }
 
         Builder {
@@ -61,10 +54,10 @@ impl<'a> Builder<'a> {
         }
     }
 
-    /// 構築したDoubleArrayをファイルに保存する
+    /// 構築したDoubleArrayを書き出す
     /// # Arguments
-    /// * `filepath`  - DoubleArrayを保存するファイルのパス
-    fn save(self, filepath: &Path) -> AppResult<()> {
+    /// * `out`     - DoubleArrayの書き出し先
+    fn save<W: Write>(self, out: W) -> AppResult<()> {
         let (tail, begs, lens) = shrinktail::shrink(self.tail, self.begs, self.lens);
 
         let mut node_size = self.chck.len();
@@ -75,41 +68,44 @@ impl<'a> Builder<'a> {
         }
         node_size += node::chck::CODE_LIMIT as usize;  // 検索時の範囲外アクセスを防ぐために、余白を設ける
         debug!("node_size: {}, begs: {}, tail: {}", node_size, begs.len(), tail.len());
-        let mut writer = BufWriter::new(File::create(filepath)?);
-        writer.write_i32::<NE>(node_size as i32)?;
-        writer.write_i32::<NE>(begs.len() as i32)?;
-        writer.write_i32::<NE>(tail.len() as i32)?;
+        let mut writer = DicWriter::new();
+        writer.write_i32::<LE>(node_size as i32)?;
+        writer.write_i32::<LE>(begs.len() as i32)?;
+        writer.write_i32::<LE>(tail.len() as i32)?;
 
         // 4byte
         for n in begs {
-            writer.write_i32::<NE>(n)?;
+            writer.write_i32::<LE>(n)?;
         }
         for i in 0..node_size {
-            writer.write_i32::<NE>(*self.base.get(i).unwrap_or(&node::base::INIT_VALUE))?;
+            writer.write_i32::<LE>(*self.base.get(i).unwrap_or(&node::base::INIT_VALUE))?;
         }
 
         // 2byte
+        writer.align::<i16>();
         for n in lens {
-            writer.write_u16::<NE>(n)?;
+            writer.write_u16::<LE>(n)?;
         }
+
+        // 1byte
         for i in 0..node_size {
-            writer.write_u16::<NE>(*self.chck.get(i).unwrap_or(&node::chck::VACANT_CODE))?;
+            writer.write_u8(*self.chck.get(i).unwrap_or(&node::chck::VACANT_CODE))?;
         }
-
-        writer.put_string(&tail)?;
-        Ok(writer.flush()?)
+        writer.write_all(&tail)?;
+        Ok(writer.finish(out)?)
     }
 
     fn build_impl(&mut self, alloca: &mut Allocator, beg: usize, end: usize, root_idx: usize) {
-        if (end - beg) == 1 {
+        // NOTE: 検索時はルートノードのBASEが非負であることを前提としているので、キーが一つだけの場合もルートはTAILにしない
+        if (end - beg) == 1 && root_idx != 0 {
             // これ以降は単一の遷移パスしかないので、まとめてTAILに挿入してしまう
             self.insert_tail(beg, root_idx);
             return;
         }
 
         let mut end_list: Vec<i32> = Vec::new();
-        let mut code_list: Utf16String = Vec::new();
-        let mut prev: Utf16Char = node::chck::VACANT_CODE;
+        let mut code_list: Vec<u8> = Vec::new();
+        let mut prev: u8 = node::chck::VACANT_CODE;
 
         // root_idxから遷移する文字を集める
         for i in beg..end {
@@ -130,7 +126,7 @@ impl<'a> Builder<'a> {
         }
     }
 
-    fn set_node(&mut self, code: Utf16Char, prev: usize, x_node: i32) -> usize {
+    fn set_node(&mut self, code: u8, prev: usize, x_node: i32) -> usize {
         let next = x_node as usize + code as usize;
         self.base.set_auto(prev, x_node, node::base::INIT_VALUE);
         self.chck.set_auto(next, code, node::chck::VACANT_CODE);
@@ -144,6 +140,6 @@ impl<'a> Builder<'a> {
 
         self.begs.push(self.tail.len() as i32);
         self.tail.extend_from_slice(rest);
-        self.lens.push(rest.len() as Utf16Char);
+        self.lens.push(rest.len() as u16);
     }
 }
@@ -1,43 +1,40 @@
-use crate::{Utf16Char, Utf16Str};
 use super::node;
 
 
-/// 文字列を文字のストリームとして扱うためのクラス。
-/// readメソッドで個々の文字を順に読み込み、文字列の終端に達した場合には
+/// バイト列を文字のストリームとして扱うためのクラス。
+/// readメソッドで個々のバイトを順に読み込み、バイト列の終端に達した場合には
 /// `node::chck::TERMINATE_CODE` が返される。
+///
+/// UTF-8の文字列は、変換せずにそのままバイト単位で読み込まれる。
 pub struct KeyStream<'a> {
-    s: &'a Utf16Str,
+    s: &'a [u8],
+    start: usize,
     cur: usize
 }
 
 impl<'a> KeyStream<'a> {
-    pub fn new(key: &Utf16Str, start: usize) -> KeyStream {
+    /// # Arguments
+    /// * `key`   - キーのバイト列
+    /// * `start` - 読み込みを開始する位置(バイト単位)
+    pub fn new(key: &[u8], start: usize) -> KeyStream<'_> {
         KeyStream {
             s: key,
+            start,
             cur: start
         }
     }
 
-    // このメソッドは動作的には、 rest().startsWith(prefix.substring(beg, len)) と等価。
-    pub fn starts_with(&self, prefix: &[Utf16Char], beg: usize, len: usize) -> bool {
-        if (self.s.len() - self.cur) < len {
-            return false;
-        }
-
-        for i in 0..len {
-            if self.s[self.cur + i] != prefix[beg + i] {
-                return false;
-            }
-        }
-
-        true
+    /// 未読のバイト列
+    pub fn rest(&self) -> &[u8] {
+        &self.s[self.cur..]
     }
 
-    pub fn rest(&self) -> &[Utf16Char] {
-        &self.s[self.cur..]
+    /// 開始位置から読み込んだバイト数
+    pub fn consumed(&self) -> usize {
+        self.cur - self.start
     }
 
-    pub fn read(&mut self) -> Utf16Char {
+    pub fn read(&mut self) -> u8 {
         if self.eos() {
             node::chck::TERMINATE_CODE
         } else {
@@ -1,9 +1,7 @@
-///! DoubleArrayのノード用の定数などが定義されているモジュール
+//! DoubleArrayのノード用の定数などが定義されているモジュール
 
 /// BASEノード用の定数およびメソッドが定義されているモジュール
 pub mod base {
-    use std::i32;
-
     /// BASEノードの初期値
     pub const INIT_VALUE: i32 = i32::MIN;
 
@@ -16,17 +14,33 @@ pub mod base {
 }
 
 /// CHECKノード用の定数が定義されているモジュール
+///
+/// キーはUTF-8のバイト単位で遷移する
 pub mod chck {
+    /// 文字列の終端を表すバイト定数
+    /// このバイト(U+0000)はシステムにより予約されており、辞書内の形態素の表層形および解析対象テキストに含まれていた場合の動作は未定義
+    pub const TERMINATE_CODE: u8 = 0u8;
+
+    /// CHECKノードが未使用だということを示すためのバイト定数
+    /// このバイト(U+0001)はシステムにより予約されており、辞書内の形態素の表層形および解析対象テキストに含まれていた場合の動作は未定義
+    pub const VACANT_CODE: u8 = 1u8;
+
+    /// 使用可能なバイトの最大値
+    pub const CODE_LIMIT: u8 = 0xFFu8;
+
+    /// キーに使用できない予約されたバイトかどうか
+    pub fn is_reserved(code: u8) -> bool {
+        code == TERMINATE_CODE || code == VACANT_CODE
+    }
+}
+
+/// 旧形式(UTF-16単位で遷移する)のDoubleArrayのCHECKノード用の定数が定義されているモジュール
+pub mod legacy_chck {
     use crate::Utf16Char;
 
     /// 文字列の終端を表す文字定数
-    /// この文字はシステムにより予約されており、辞書内の形態素の表層形および解析対象テキストに含まれていた場合の動作は未定義
     pub const TERMINATE_CODE: Utf16Char = 0u16;
 
     /// CHECKノードが未使用だということを示すための文字定数
-    /// この文字はシステムにより予約されており、辞書内の形態素の表層形および解析対象テキストに含まれていた場合の動作は未定義
     pub const VACANT_CODE: Utf16Char = 1u16;
-
-    /// 使用可能な文字の最大値
-    pub const CODE_LIMIT: Utf16Char = 0xFFFFu16;
 }
@@ -1,48 +1,86 @@
-use std::fs::File;
-use std::io::{self, BufReader};
-use std::path::Path;
+use std::collections::HashMap;
+use std::io;
 
-use byteorder::{NativeEndian as NE, ReadBytesExt};
-
-use crate::util::InputUtil;
-use crate::{Utf16Char, Utf16Str};
+use crate::storage::{Array, DicFile, LEGACY_FORMAT_VERSION};
+use crate::Utf16Char;
 
+use super::builder;
 use super::keystream::KeyStream;
 use super::node;
 
 /// DoubleArray検索用のstruct
+///
+/// キーはUTF-8のバイト単位で遷移するため、解析対象テキストを変換せずに検索できる
 #[derive(Clone)]
 pub struct Searcher {
     key_set_size: usize,
-    base: Box<[i32]>,
-    chck: Box<[Utf16Char]>,
-    begs: Box<[i32]>,
-    lens: Box<[i16]>,
-    tail: Box<[Utf16Char]>,
+    base: Array<i32>,
+    chck: Array<u8>,
+    begs: Array<i32>,
+    lens: Array<i16>,
+    tail: Array<u8>,
+    /// 旧形式の辞書から再構築した場合の、キーのIDから旧形式のキーのIDへの対応
+    legacy_ids: Option<Box<[i32]>>,
 }
 
 impl Searcher {
     /// 保存されているDoubleArrayを読み込んで、このstructのインスタンスを作成する
-    pub fn new<R: io::Read>(src: R) -> io::Result<Searcher> {
-        let mut reader = BufReader::new(src);
+    pub fn new<R: io::Read>(mut src: R) -> io::Result<Searcher> {
+        let mut buf = Vec::new();
+        src.read_to_end(&mut buf)?;
+        Self::from_dic_file(DicFile::from_bytes("trie", buf)?)
+    }
 
-        let node_sz = reader.read_i32::<NE>()?;
-        let tind_sz = reader.read_i32::<NE>()?;
-        let tail_sz = reader.read_i32::<NE>()?;
+    /// 開いた辞書ファイルからDoubleArrayを読み込んで、このstructのインスタンスを作成する
+    ///
+    /// 旧形式のファイルはUTF-16単位で遷移するDoubleArrayのため、読み込み時にUTF-8単位で再構築する
+    pub fn from_dic_file(mut file: DicFile) -> io::Result<Searcher> {
+        if file.version() == LEGACY_FORMAT_VERSION {
+            return Self::from_legacy_dic_file(file);
+        }
+
+        let node_sz = file.read_value::<i32>()?;
+        let tind_sz = file.read_value::<i32>()?;
+        let tail_sz = file.read_value::<i32>()?;
         //        debug!("tind_sz: {}, node_sz: {}, tail_sz: {}", tind_sz, node_sz, tail_sz);
 
         Ok(Searcher {
             key_set_size: tind_sz as usize,
-            begs: reader.get_int_array(tind_sz as usize)?,
-            base: reader.get_int_array(node_sz as usize)?,
-            lens: reader.get_short_array(tind_sz as usize)?,
-            chck: reader.get_char_array(node_sz as usize)?,
-            tail: reader.get_string(tail_sz as usize)?,
+            begs: file.read_array(tind_sz as usize)?,
+            base: file.read_array(node_sz as usize)?,
+            lens: file.read_array(tind_sz as usize)?,
+            chck: file.read_array(node_sz as usize)?,
+            tail: file.read_array(tail_sz as usize)?,
+            legacy_ids: None,
         })
     }
 
-    pub fn from_path(file_path: &Path) -> io::Result<Searcher> {
-        Self::new(File::open(file_path)?)
+    /// 旧形式のDoubleArrayから全てのキーを取り出し、UTF-8単位のDoubleArrayを構築する
+    fn from_legacy_dic_file(mut file: DicFile) -> io::Result<Searcher> {
+        let node_sz = file.read_value::<i32>()? as usize;
+        let tind_sz = file.read_value::<i32>()? as usize;
+        let tail_sz = file.read_value::<i32>()? as usize;
+        let begs = file.read_array::<i32>(tind_sz)?;
+        let base = file.read_array::<i32>(node_sz)?;
+        let lens = file.read_array::<i16>(tind_sz)?;
+        let chck = file.read_array::<Utf16Char>(node_sz)?;
+        let tail = file.read_array::<Utf16Char>(tail_sz)?;
+
+        let keys = legacy_keys(&base, &chck, &begs, &lens, &tail)?;
+        let mut trie_bytes = Vec::new();
+        builder::build_to_writer(
+            keys.iter().map(|(key, _)| key.clone()).collect(),
+            &mut trie_bytes,
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut trie = Self::new(trie_bytes.as_slice())?;
+
+        let mut legacy_ids = vec![-1; trie.size()];
+        for (key, legacy_id) in keys {
+            legacy_ids[trie.search(&key) as usize] = legacy_id;
+        }
+        trie.legacy_ids = Some(legacy_ids.into_boxed_slice());
+        Ok(trie)
     }
 
     /// DoubleArrayに格納されているキーの数を返す
@@ -51,24 +89,31 @@ impl Searcher {
     }
 
     /// キーを検索する
-    /// キーが見つかった場合はそのIDを、見つからなかった場合は-1を返す
+    /// キーが見つかった場合はそのIDを、見つからなかった場合は-1を返す.
+    /// 予約されたバイト(U+0000, U+0001)を含むキーは格納されないため、常に-1を返す
     /// # Arguments
     /// * `key` - 検索対象のキー文字列
-    pub fn search(&self, key: &Utf16Str) -> i32 {
+    pub fn search(&self, key: &str) -> i32 {
+        if key.bytes().any(node::chck::is_reserved) {
+            return -1;
+        }
         let mut node = self.base[0];
-        let mut input = KeyStream::new(key, 0);
+        let mut input = KeyStream::new(key.as_bytes(), 0);
 
         loop {
             let code = input.read();
             let idx = node as usize + code as usize;
+            if self.chck.get(idx) != Some(&code) {
+                return -1;
+            }
             node = self.base[idx];
 
-            if self.chck[idx] == code {
-                if node >= 0 {
-                    continue;
-                } else if input.eos() || self.key_exists(&input, node) {
-                    return node::base::ID(node);
-                }
+            if node == node::base::INIT_VALUE {
+                return -1;
+            } else if node >= 0 {
+                continue;
+            } else if input.eos() || self.key_exists(&input, node) {
+                return self.key_id(node::base::ID(node));
             }
             return -1;
         }
@@ -76,71 +121,235 @@ impl Searcher {
 
     /// common-prefix検索を行う
     ///
-    /// 条件に一致するキーが見つかる度に、callbackが呼び出される
+    /// 条件に一致するキーが見つかる度に、callbackが呼び出される.
+    /// キーは文字単位で格納されているため、一致した長さは常に文字の境界となる
     /// # Arguments
-    /// * `key` - 検索対象のキー文字列
-    /// * `start` - 検索対象となるキー文字列の最初の添字
-    /// * `callback` - 一致を検出した場合に呼び出されるクロージャー
-    pub fn each_common_prefix<F>(&self, key: &Utf16Str, start: usize, mut callback: F)
+    /// * `text` - 検索対象の文字列
+    /// * `start` - 検索を開始する位置(バイト単位)
+    /// * `callback` - 一致を検出した場合に呼び出されるクロージャー. 開始位置、一致した長さ(バイト単位)、キーのIDを受け取る
+    pub fn each_common_prefix<F>(&self, text: &str, start: usize, mut callback: F)
     where
-        F: FnMut(usize, i32, i32) -> (),
+        F: FnMut(usize, usize, i32),
     {
         let mut node = self.base[0];
-        let mut offset: i32 = -1;
-        let mut input = KeyStream::new(key, start);
+        let mut input = KeyStream::new(text.as_bytes(), start);
 
         loop {
+            let consumed = input.consumed();
             let code = input.read();
-            offset += 1;
             let terminal_idx = (node as usize) + (node::chck::TERMINATE_CODE as usize);
 
             if self.chck[terminal_idx] == node::chck::TERMINATE_CODE {
-                callback(start, offset, node::base::ID(self.base[terminal_idx]));
+                callback(
+                    start,
+                    consumed,
+                    self.key_id(node::base::ID(self.base[terminal_idx])),
+                );
                 if code == node::chck::TERMINATE_CODE {
                     return;
                 }
             }
 
+            // 未使用のノードのCHECKはVACANT_CODEと一致するため、予約されたバイトでは遷移しない
+            if node::chck::is_reserved(code) {
+                return;
+            }
             let idx = (node as usize) + (code as usize);
+            if self.chck.get(idx) != Some(&code) {
+                return;
+            }
             node = self.base[idx];
-            if self.chck[idx] == code {
-                if node >= 0 {
-                    continue;
-                } else {
-                    self.call_if_key_including(&input, node, start, offset, callback);
-                }
+            if node == node::base::INIT_VALUE {
+                return;
+            } else if node >= 0 {
+                continue;
+            } else {
+                self.call_if_key_including(&input, node, start, callback);
             }
             return;
         }
     }
 
-    fn call_if_key_including<F>(
-        &self,
-        input: &KeyStream,
-        node: i32,
-        start: usize,
-        offset: i32,
-        mut callback: F,
-    ) where
-        F: FnMut(usize, i32, i32) -> (),
+    fn call_if_key_including<F>(&self, input: &KeyStream, node: i32, start: usize, mut callback: F)
+    where
+        F: FnMut(usize, usize, i32),
     {
         let id = node::base::ID(node) as usize;
         if self.begs.len() <= id || self.lens.len() <= id {
             return;
         }
-        if input.starts_with(
-            self.tail.as_ref(),
-            self.begs[id] as usize,
-            self.lens[id] as usize,
-        ) {
-            callback(start, offset + i32::from(self.lens[id]) + 1, id as i32);
+        let s = self.tail_of(id);
+        if input.rest().starts_with(s) {
+            callback(start, input.consumed() + s.len(), self.key_id(id as i32));
         }
     }
 
     fn key_exists(&self, input: &KeyStream, node: i32) -> bool {
         let id = node::base::ID(node) as usize;
-        let s =
-            &self.tail[(self.begs[id] as usize)..(self.begs[id] as usize + self.lens[id] as usize)];
-        *input.rest() == *s
+        id < self.begs.len() && id < self.lens.len() && *input.rest() == *self.tail_of(id)
+    }
+
+    fn tail_of(&self, id: usize) -> &[u8] {
+        &self.tail[(self.begs[id] as usize)..(self.begs[id] as usize + self.lens[id] as usize)]
+    }
+
+    /// DoubleArray内のIDを、辞書の他のファイルが参照するキーのIDに変換する
+    fn key_id(&self, id: i32) -> i32 {
+        match self.legacy_ids {
+            Some(ref ids) => ids[id as usize],
+            None => id,
+        }
+    }
+}
+
+/// 旧形式のDoubleArrayに格納されている全てのキーと、そのIDを返す
+fn legacy_keys(
+    base: &[i32],
+    chck: &[Utf16Char],
+    begs: &[i32],
+    lens: &[i16],
+    tail: &[Utf16Char],
+) -> io::Result<Vec<(String, i32)>> {
+    use super::node::legacy_chck::{TERMINATE_CODE, VACANT_CODE};
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "word2id: broken legacy trie");
+
+    // 遷移先のノードから、BASEの値を介して遷移元のノードを求める
+    let mut parents: HashMap<i32, usize> = HashMap::new();
+    for (idx, &b) in base.iter().enumerate() {
+        if b >= 0 && (idx == 0 || chck[idx] != VACANT_CODE) {
+            parents.insert(b, idx);
+        }
+    }
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); base.len()];
+    for (idx, &code) in chck.iter().enumerate().skip(1) {
+        if code == VACANT_CODE {
+            continue;
+        }
+        if let Some(&parent) = parents.get(&(idx as i32 - i32::from(code))) {
+            children[parent].push(idx);
+        }
+    }
+
+    let mut keys = Vec::new();
+    let mut stack: Vec<(usize, Vec<Utf16Char>)> = vec![(0, Vec::new())];
+    while let Some((node, key)) = stack.pop() {
+        for &idx in &children[node] {
+            let mut key = key.clone();
+            if chck[idx] != TERMINATE_CODE {
+                key.push(chck[idx]);
+            }
+            if base[idx] >= 0 {
+                stack.push((idx, key));
+                continue;
+            }
+            let id = node::base::ID(base[idx]);
+            let (beg, len) = (
+                *begs.get(id as usize).ok_or_else(invalid)? as usize,
+                *lens.get(id as usize).ok_or_else(invalid)? as usize,
+            );
+            key.extend_from_slice(tail.get(beg..(beg + len)).ok_or_else(invalid)?);
+            keys.push((String::from_utf16(&key).map_err(|_| invalid())?, id));
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Searcher;
+    use crate::trie::builder;
+    use crate::trie::node;
+
+    fn prefixes(trie: &Searcher, text: &str, start: usize) -> Vec<(usize, i32)> {
+        let mut found = Vec::new();
+        trie.each_common_prefix(text, start, |_, length, id| found.push((length, id)));
+        found
+    }
+
+    #[test]
+    fn test_search() {
+        let keys = ["あ", "あい", "い", "\u{1F351}", "\u{1F351}あ"];
+        let mut bytes = Vec::new();
+        builder::build_to_writer(keys.iter().map(|s| s.to_string()).collect(), &mut bytes).unwrap();
+        let trie = Searcher::new(bytes.as_slice()).unwrap();
+
+        assert_eq!(keys.len(), trie.size());
+        // IDはキーをバイト列として並べた順位
+        let mut sorted = keys.to_vec();
+        sorted.sort();
+        for (id, key) in sorted.iter().enumerate() {
+            assert_eq!(id as i32, trie.search(key));
+        }
+        assert_eq!(-1, trie.search("う"));
+        assert_eq!(-1, trie.search("あいう"));
+        assert_eq!(-1, trie.search(""));
+        // 予約されたバイトは未使用のノードや終端と一致しない
+        assert_eq!(-1, trie.search("\u{1}"));
+        assert_eq!(-1, trie.search("\u{1}\u{1}"));
+        assert_eq!(-1, trie.search("あ\0"));
+        assert_eq!(-1, trie.search("あ\u{1}"));
+
+        // 一致した長さはバイト単位
+        let id = |key: &str| trie.search(key);
+        assert_eq!(
+            vec![(3, id("あ")), (6, id("あい"))],
+            prefixes(&trie, "あいう", 0)
+        );
+        assert_eq!(vec![(3, id("い"))], prefixes(&trie, "あいう", 3));
+        assert_eq!(
+            vec![(4, id("\u{1F351}")), (7, id("\u{1F351}あ"))],
+            prefixes(&trie, "x\u{1F351}あい", 1)
+        );
+        assert!(prefixes(&trie, "あいう", 6).is_empty());
+        assert!(prefixes(&trie, "\u{1}\u{1}", 0).is_empty());
+        assert_eq!(vec![(3, id("あ"))], prefixes(&trie, "あ\u{1}い", 0));
+    }
+
+    #[test]
+    fn test_legacy_trie() {
+        // UTF-16単位で遷移する旧形式のDoubleArray: "あ"(ID 0), "あい"(ID 1), "𝄞"(ID 2)
+        let node_size = 0x20000;
+        let mut base = vec![node::base::INIT_VALUE; node_size];
+        let mut chck = vec![node::legacy_chck::VACANT_CODE; node_size];
+        let mut set = |idx: usize, code: u16, b: i32| {
+            chck[idx] = code;
+            base[idx] = b;
+        };
+        let (root_x, a_x) = (10, 60000);
+        let a = root_x + 0x3042;
+        set(a, 0x3042, a_x as i32);
+        set(root_x + 0xD834, 0xD834, node::base::ID(2));
+        set(a_x, node::legacy_chck::TERMINATE_CODE, node::base::ID(0));
+        set(a_x + 0x3044, 0x3044, node::base::ID(1));
+        base[0] = root_x as i32;
+        let (begs, lens, tail) = ([0i32, 0, 0], [0i16, 0, 1], [0xDD1Eu16]);
+
+        // 旧形式のファイルはヘッダが無く、ネイティブエンディアンで格納されている
+        let mut bytes = Vec::new();
+        for n in [node_size as i32, begs.len() as i32, tail.len() as i32]
+            .iter()
+            .chain(&begs)
+        {
+            bytes.extend_from_slice(&n.to_ne_bytes());
+        }
+        for n in &base {
+            bytes.extend_from_slice(&n.to_ne_bytes());
+        }
+        for n in &lens {
+            bytes.extend_from_slice(&n.to_ne_bytes());
+        }
+        for n in chck.iter().chain(&tail) {
+            bytes.extend_from_slice(&n.to_ne_bytes());
+        }
+
+        let trie = Searcher::new(bytes.as_slice()).unwrap();
+        assert_eq!(3, trie.size());
+        assert_eq!(0, trie.search("あ"));
+        assert_eq!(1, trie.search("あい"));
+        assert_eq!(2, trie.search("\u{1D11E}"));
+        assert_eq!(-1, trie.search("い"));
+        assert_eq!(vec![(3, 0), (6, 1)], prefixes(&trie, "あいう", 0));
+        assert_eq!(vec![(4, 2)], prefixes(&trie, "\u{1D11E}あ", 0));
     }
 }
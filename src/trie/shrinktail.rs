@@ -1,5 +1,4 @@
 use std::cmp;
-use crate::{Utf16Char, Utf16String};
 
 
 /// TAIL配列(文字列)の圧縮を行うクラス
@@ -7,9 +6,9 @@ use crate::{Utf16Char, Utf16String};
 ///
 /// 圧縮対象となるTAIL配列および、TAIL配列へのインデックスを渡してインスタンスを初期化する。
 /// 引数に渡した各オブジェクトはshrinkメソッドの呼び出しに伴い、破壊的に更新される。
-pub fn shrink(tail: Utf16String, mut begs: Vec<i32>, mut lens: Vec<Utf16Char>)
-              -> (Utf16String, Vec<i32>, Vec<Utf16Char>) {
-    let mut new_tail: Utf16String;
+pub fn shrink(tail: Vec<u8>, mut begs: Vec<i32>, mut lens: Vec<u16>)
+              -> (Vec<u8>, Vec<i32>, Vec<u16>) {
+    let mut new_tail: Vec<u8>;
     {
         // TAILに格納されている文字列群を、その末尾から比較してソートする
         let sorted: Vec<TailString> = {
@@ -39,7 +38,7 @@ pub fn shrink(tail: Utf16String, mut begs: Vec<i32>, mut lens: Vec<Utf16Char>)
 
             // TAIL配列へのポインタを更新する
             begs[ts.id] = beg_index as i32;
-            lens[ts.id] = ts.s.len() as Utf16Char;
+            lens[ts.id] = ts.s.len() as u16;
         }
     }
 
@@ -48,7 +47,7 @@ pub fn shrink(tail: Utf16String, mut begs: Vec<i32>, mut lens: Vec<Utf16Char>)
 
 struct TailString<'a> {
     id: usize,
-    s: &'a [Utf16Char]
+    s: &'a [u8]
 }
 
 impl<'a> cmp::Ord for TailString<'a> {
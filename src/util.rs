@@ -1,100 +1,47 @@
+use std::collections::HashMap;
 use std::convert::From;
 use std::error::Error;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader};
-use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use byteorder::{NativeEndian as NE, ReadBytesExt, WriteBytesExt};
 use encoding_rs::*;
 
 use crate::dictionary::build::*;
-use crate::Utf16Char;
-
-pub trait InputUtil: io::Read {
-    fn get_int(&mut self) -> io::Result<i32> {
-        self.read_i32::<NE>()
-    }
-
-    fn get_int_array(&mut self, count: usize) -> io::Result<Box<[i32]>> {
-        let mut v = vec![0i32; count];
-        for i in 0..count {
-            v[i] = self.read_i32::<NE>()?;
-        }
-        Ok(v.into_boxed_slice())
-    }
-
-    fn get_short_array(&mut self, count: usize) -> io::Result<Box<[i16]>> {
-        let mut v = vec![0i16; count];
-        for i in 0..count {
-            v[i] = self.read_i16::<NE>()?;
-        }
-        Ok(v.into_boxed_slice())
-    }
-
-    fn get_char_array(&mut self, count: usize) -> io::Result<Box<[Utf16Char]>> {
-        let mut v = vec![0u16; count];
-        for i in 0..count {
-            v[i] = self.read_u16::<NE>()?;
-        }
-        Ok(v.into_boxed_slice())
-    }
-
-    fn get_string(&mut self, count: usize) -> io::Result<Box<[Utf16Char]>> {
-        let mut v = vec![0u16; count];
-        for i in 0..count {
-            v[i] = self.read_u16::<NE>()?;
-        }
-        Ok(v.into_boxed_slice())
-    }
-}
-
-impl<R: io::Read + ?Sized> InputUtil for R {}
-
-pub fn read_all_as_chars(dir: &mut dyn DirLike, path: &str) -> io::Result<Box<[Utf16Char]>> {
-    let file_len = dir.file_size(path)?;
-    let file = dir.open(path)?;
-    let mut reader = BufReader::new(file);
-    reader.get_string((file_len as usize) / mem::size_of::<u16>())
-}
-
-pub fn read_all_as_int_array(dir: &mut dyn DirLike, path: &str) -> io::Result<Box<[i32]>> {
-    let file_len = dir.file_size(path)?;
-    let file = dir.open(path)?;
-    let mut reader = BufReader::new(file);
-    reader.get_int_array((file_len as usize) / mem::size_of::<i32>())
-}
-
-pub trait OutputUtil: io::Write {
-    fn put_string(&mut self, str: &[Utf16Char]) -> io::Result<()> {
-        for i in 0..str.len() {
-            self.write_u16::<NE>(str[i])?;
-        }
-        Ok(())
-    }
-}
-
-impl<W: io::Write + ?Sized> OutputUtil for W {}
+use crate::storage::DicFile;
 
 pub struct ReadLine<'a> {
-    reader: BufReader<File>,
+    reader: Box<dyn BufRead + 'a>,
     line_number: i32,
-    path: &'a Path,
+    path: PathBuf,
     decoder: Option<&'static Encoding>,
     encoded_buf: Vec<u8>,
 }
 
 impl<'a> ReadLine<'a> {
-    pub fn new(file_path: &'a Path, encoding_name: &str) -> AppResult<ReadLine<'a>> {
+    pub fn new(file_path: &Path, encoding_name: &str) -> AppResult<ReadLine<'a>> {
         let file = File::open(file_path)?;
+        Self::from_reader(BufReader::new(file), file_path, encoding_name)
+    }
+
+    /// ファイル以外の読み込み元から行を読み込む
+    /// # Arguments
+    /// * `reader`        - 読み込み元
+    /// * `name`          - エラーの報告に使用する、読み込み元の名前
+    /// * `encoding_name` - 文字列エンコーディング
+    pub fn from_reader<R: BufRead + 'a>(
+        reader: R,
+        name: &Path,
+        encoding_name: &str,
+    ) -> AppResult<ReadLine<'a>> {
         let encoding = Encoding::for_label(encoding_name.as_bytes())
             .ok_or_else(|| format!("Unknown encoding; {}", encoding_name))?;
 
         //        debug!("encoding.name: {}", encoding.name());
         Ok(ReadLine {
-            reader: BufReader::new(file),
+            reader: Box::new(reader),
             line_number: 0,
-            path: file_path,
+            path: name.to_path_buf(),
             decoder: if encoding != UTF_8 {
                 Some(encoding)
             } else {
@@ -154,10 +101,20 @@ impl<'a> ReadLine<'a> {
         }
     }
 
+    /// 読み込み元のパス(または名前)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 最後に読み込んだ行の行番号(1始まり)
+    pub fn line_number(&self) -> i32 {
+        self.line_number
+    }
+
     pub fn parse_error<S: Into<String>>(&self, msg: S) -> AppError {
         AppError::Parse {
             message: msg.into(),
-            path: self.path.to_path_buf(),
+            path: self.path.clone(),
             line_number: self.line_number,
         }
     }
@@ -175,6 +132,13 @@ pub trait DirLike {
     fn file_size(&mut self, path: &str) -> io::Result<u64>;
     /// 指定したパスのファイルを開く
     fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>>;
+
+    /// 指定したパスのファイルを辞書ファイルとして開く
+    ///
+    /// 既定の実装では、`open`で開いたファイルの内容を読み込んで複製する
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        DicFile::read(path, self.open(path)?)
+    }
 }
 
 /// DirLike implement for Local file system
@@ -190,6 +154,102 @@ impl DirLike for &Path {
     }
 }
 
+/// DirLike implement for in-memory files
+///
+/// キーはファイルのパス、値はファイルの内容
+impl DirLike for HashMap<String, Vec<u8>> {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        Ok(get_file(self, path)?.len() as u64)
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        Ok(Box::new(io::Cursor::new(get_file(self, path)?.clone())))
+    }
+
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        DicFile::from_bytes(path, get_file(self, path)?.clone())
+    }
+}
+
+fn get_file<'a>(files: &'a HashMap<String, Vec<u8>>, path: &str) -> io::Result<&'a Vec<u8>> {
+    files
+        .get(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{}: not found", path)))
+}
+
+/// 別の`DirLike`内のサブディレクトリ
+///
+/// アーカイブ内の`ipadic/word2id`のように、サブディレクトリに置かれたバイナリ辞書を読み込む場合に使用する
+pub struct SubDir<D: DirLike> {
+    dir: D,
+    prefix: String,
+}
+
+impl<D: DirLike> SubDir<D> {
+    /// コンストラクタ
+    /// # Arguments
+    /// * `dir`    - 親ディレクトリ
+    /// * `prefix` - サブディレクトリのパス(例: `ipadic`). 区切り文字は`/`
+    pub fn new(dir: D, prefix: &str) -> SubDir<D> {
+        let prefix = prefix.trim_end_matches('/');
+        SubDir {
+            dir,
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", prefix)
+            },
+        }
+    }
+
+    /// 親ディレクトリを返す
+    pub fn into_inner(self) -> D {
+        self.dir
+    }
+
+    fn path(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+}
+
+impl<D: DirLike> DirLike for SubDir<D> {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        let path = self.path(path);
+        self.dir.file_size(&path)
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        let path = self.path(path);
+        self.dir.open(&path)
+    }
+
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        let path = self.path(path);
+        self.dir.open_dic_file(&path)
+    }
+}
+
+/// 辞書ファイルをメモリマップして読み込む、ローカルファイルシステム上のディレクトリ
+///
+/// 辞書の配列は複製されずにマップしたページを直接参照するため、同じ辞書を読み込む複数のプロセスでページが共有される
+#[cfg(feature = "mmap")]
+pub struct MmapDir<'a>(pub &'a Path);
+
+#[cfg(feature = "mmap")]
+impl DirLike for MmapDir<'_> {
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        self.0.file_size(path)
+    }
+
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read>> {
+        self.0.open(path)
+    }
+
+    fn open_dic_file(&mut self, path: &str) -> io::Result<DicFile> {
+        DicFile::map(&self.0.join(path))
+    }
+}
+
 #[allow(dead_code)]
 pub mod debug {
     use std::fs::File;